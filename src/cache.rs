@@ -0,0 +1,1492 @@
+// The SQLite cache: a table for each mode and format, plus the history and misses
+// Built only with the "cache" feature (on by default); see no_cache.rs for the stand-ins
+
+use core::time::Duration;
+use std::path::{Path, PathBuf};
+use std::{fs, thread};
+
+use anyhow::{anyhow, Context};
+use gloss_word::{cache_key, loose_key, Format, Mode, Validators};
+use indicatif::HumanBytes;
+use rusqlite::{params, ErrorCode};
+use serde::{Deserialize, Serialize};
+
+use crate::{unix_now, Headword};
+
+pub use rusqlite::Connection;
+
+// Function to make sure the cache db opens, and that SQLite finds nothing wrong with it
+pub fn check_cache_db(db_path: &Path) -> Result<String, anyhow::Error> {
+    let db_conn = open_db(db_path).context("Failed to open it")?;
+    create_tables(&db_conn).context("Failed to prepare it")?;
+
+    let status: String = db_conn
+        .query_row("PRAGMA quick_check", [], |row| row.get(0))
+        .context("Failed to check it")?;
+
+    if status != "ok" {
+        return Err(anyhow!("Integrity check failed: {status}"));
+    }
+
+    Ok(format!("{} is in order", db_path.display()))
+}
+
+// Function to report on the contents of the cache db
+pub fn print_cache_stats(db_path: &Path) -> Result<(), anyhow::Error> {
+    if !db_path.exists() {
+        return Err(anyhow!("Cache database not found"));
+    }
+
+    let db_size = fs::metadata(db_path)
+        .context("Failed to read cache database metadata")?
+        .len();
+
+    let db_conn = open_db(db_path).context("Failed to open cache database")?;
+    create_tables(&db_conn).context("Failed to prepare cache database")?;
+
+    let count_entries = |table: &str| -> Result<i64, rusqlite::Error> {
+        db_conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })
+    };
+
+    // Count entries in all formats together
+    let count_all = |tables: [&str; 3]| -> Result<i64, rusqlite::Error> {
+        tables.into_iter().map(count_entries).sum()
+    };
+
+    let dic_count = count_all(DICTIONARY_TABLES)?;
+    let etym_count = count_all(ETYMOLOGY_TABLES)?;
+    let miss_count = count_entries("not_found")?;
+
+    // Let SQLite format the timestamps; None if the cache is empty
+    let all_timestamps = DICTIONARY_TABLES
+        .into_iter()
+        .chain(ETYMOLOGY_TABLES)
+        .map(|table| format!("SELECT created_at FROM {table}"))
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+
+    let (oldest, newest): (Option<String>, Option<String>) = db_conn.query_row(
+        &format!(
+            "SELECT datetime(MIN(created_at), 'unixepoch'), datetime(MAX(created_at), 'unixepoch')
+                FROM ({all_timestamps})"
+        ),
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+
+    println!("Database:           {}", db_path.display());
+    println!("Database size:      {}", HumanBytes(db_size));
+    println!("Dictionary entries: {dic_count}");
+    println!("Etymology entries:  {etym_count}");
+    println!("Cached misses:      {miss_count}");
+
+    if let (Some(oldest), Some(newest)) = (oldest, newest) {
+        println!("Oldest entry:       {oldest} UTC");
+        println!("Newest entry:       {newest} UTC");
+    }
+
+    Ok(())
+}
+
+// A cached entry, in portable form, for --export and --import
+#[derive(Debug, Deserialize, PartialEq, Eq, Serialize)]
+struct ExportRow {
+    word: String,
+    mode: String,
+    format: String,
+    content: String,
+    pronunciation: Option<String>,
+    #[serde(default)]
+    inflections: Option<String>,
+    #[serde(default)]
+    also_called: Option<String>,
+    #[serde(default)]
+    usage_notes: Option<String>,
+    #[serde(default)]
+    html: Option<String>,
+    created_at: i64,
+}
+
+// Every mode and format that has a cache table
+fn cache_kinds() -> impl Iterator<Item = (Mode, Format)> {
+    [Mode::Definition, Mode::Etymology]
+        .into_iter()
+        .flat_map(|mode| {
+            [Format::Plain, Format::Markdown, Format::Html].map(|format| (mode, format))
+        })
+}
+
+// Function to write every cached entry to a JSON or CSV file
+pub fn export_cache(db_path: &Path, export_path: &Path, csv: bool) -> Result<(), anyhow::Error> {
+    if !db_path.exists() {
+        return Err(anyhow!("Cache database not found"));
+    }
+
+    let db_conn = open_db(db_path).context("Failed to open cache database")?;
+    create_tables(&db_conn).context("Failed to prepare cache database")?;
+
+    let mut rows = Vec::new();
+
+    for (mode, format) in cache_kinds() {
+        let table = table_name(mode, format);
+        let mut stmt = db_conn.prepare(&format!(
+            "SELECT word, content, pronunciation, inflections, also_called, usage_notes, html,
+                created_at FROM {table} ORDER BY word"
+        ))?;
+
+        let table_rows = stmt.query_map([], |row| {
+            Ok(ExportRow {
+                word: row.get(0)?,
+                mode: mode.name().to_owned(),
+                format: format.name().to_owned(),
+                content: row.get(1)?,
+                pronunciation: row.get(2)?,
+                inflections: row.get(3)?,
+                also_called: row.get(4)?,
+                usage_notes: row.get(5)?,
+                html: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })?;
+
+        for row in table_rows {
+            rows.push(row?);
+        }
+    }
+
+    let file = fs::File::create(export_path).context("Failed to create export file")?;
+
+    if csv {
+        let mut writer = csv::Writer::from_writer(file);
+        for row in &rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+    } else {
+        serde_json::to_writer_pretty(file, &rows)?;
+    }
+
+    eprintln!(
+        "Exported {} entries to {}",
+        rows.len(),
+        export_path.display()
+    );
+    Ok(())
+}
+
+// Function to add entries from an exported file, leaving existing ones alone
+pub fn import_cache(db_path: &Path, import_path: &Path) -> Result<(), anyhow::Error> {
+    let file = fs::File::open(import_path).context("Failed to open import file")?;
+
+    let rows: Vec<ExportRow> = if import_path.extension().is_some_and(|ext| ext == "csv") {
+        csv::Reader::from_reader(file)
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .context("Failed to parse CSV")?
+    } else {
+        serde_json::from_reader(file).context("Failed to parse JSON")?
+    };
+
+    let mut db_conn = open_db(db_path).context("Failed to open cache database")?;
+    create_tables(&db_conn).context("Failed to prepare cache database")?;
+
+    // All or nothing, and much faster than a transaction per row
+    let tx = db_conn.transaction()?;
+    let mut imported = 0;
+
+    for row in &rows {
+        let Some((mode, format)) = cache_kinds()
+            .find(|(mode, format)| mode.name() == row.mode && format.name() == row.format)
+        else {
+            return Err(anyhow!("Unknown entry type: {} ({})", row.mode, row.format));
+        };
+
+        let table = table_name(mode, format);
+        imported += tx.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {table}
+                    (word, content, created_at, accessed_at, pronunciation, inflections,
+                        also_called, usage_notes, html)
+                    VALUES (?1, ?2, ?3, ?3, ?4, ?5, ?6, ?7, ?8)"
+            ),
+            params![
+                row.word,
+                row.content,
+                row.created_at,
+                row.pronunciation,
+                row.inflections,
+                row.also_called,
+                row.usage_notes,
+                row.html
+            ],
+        )?;
+    }
+
+    tx.commit()?;
+
+    eprintln!(
+        "Imported {imported} entries ({} duplicates skipped)",
+        rows.len() - imported
+    );
+    Ok(())
+}
+
+// How long to wait for another connection (or process) to finish writing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Function to open the cache db, waiting politely if it's locked
+// WAL lets readers and a writer (even in another process) work at the same time;
+// its -wal and -shm files sit next to the db, so --clear-cache takes them too
+fn open_db(db_path: &Path) -> Result<Connection, rusqlite::Error> {
+    let db_conn = Connection::open(db_path)?;
+    db_conn.busy_timeout(BUSY_TIMEOUT)?;
+    db_conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    db_conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(db_conn)
+}
+
+// Function to run a write, retrying a few times if the db is still locked
+// The busy timeout covers most contention; this is for anything that outlasts it
+fn retry_busy<T>(
+    mut write: impl FnMut() -> Result<T, rusqlite::Error>,
+) -> Result<T, rusqlite::Error> {
+    let mut attempt = 0;
+
+    loop {
+        match write() {
+            Err(rusqlite::Error::SqliteFailure(e, _))
+                if attempt < 3
+                    && matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) =>
+            {
+                attempt += 1;
+                thread::sleep(Duration::from_millis(100 << attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+// Function to open the cache db, making sure its tables exist
+// A corrupt db is set aside as entries.sqlite.bad and replaced, so caching heals itself
+pub fn open_cache(db_path: &Path) -> Option<Connection> {
+    match open_db(db_path).and_then(|db_conn| create_tables(&db_conn).map(|()| db_conn)) {
+        Ok(db_conn) => Some(db_conn),
+        Err(e) if is_corrupt(&e) => {
+            let bad_path = sibling_path(db_path, ".bad");
+            fs::rename(db_path, &bad_path).ok()?;
+
+            for suffix in ["-wal", "-shm"] {
+                let _remove = fs::remove_file(sibling_path(db_path, suffix));
+            }
+
+            eprintln!(
+                "Warning: Cache database was corrupt; moved it to {} and started afresh",
+                bad_path.display()
+            );
+
+            let db_conn = open_db(db_path).ok()?;
+            let _create = create_tables(&db_conn);
+            Some(db_conn)
+        }
+        Err(_) => None,
+    }
+}
+
+// Function to tell whether an error means the db file itself is unusable
+const fn is_corrupt(error: &rusqlite::Error) -> bool {
+    matches!(
+        error,
+        rusqlite::Error::SqliteFailure(e, _)
+            if matches!(e.code, ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase)
+    )
+}
+
+// Function to name a file beside the db, e.g. entries.sqlite-wal
+fn sibling_path(db_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = db_path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// Function to create the cache tables, if they don't exist
+fn create_tables(db_conn: &Connection) -> Result<(), rusqlite::Error> {
+    for table in DICTIONARY_TABLES.into_iter().chain(ETYMOLOGY_TABLES) {
+        db_conn.execute(
+            &format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    word           TEXT UNIQUE NOT NULL,
+                    content        TEXT NOT NULL,
+                    created_at     INTEGER NOT NULL,
+                    pronunciation  TEXT,
+                    inflections    TEXT,
+                    accessed_at    INTEGER NOT NULL DEFAULT 0,
+                    also_called    TEXT,
+                    usage_notes    TEXT,
+                    html           TEXT,
+                    etag           TEXT,
+                    last_modified  TEXT
+                )"
+            ),
+            [],
+        )?;
+
+        // Tables from older versions lack a timestamp; start their clocks now
+        if db_conn
+            .prepare(&format!("SELECT created_at FROM {table}"))
+            .is_err()
+        {
+            db_conn.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0"),
+                [],
+            )?;
+            db_conn.execute(&format!("UPDATE {table} SET created_at = ?1"), [unix_now()])?;
+        }
+
+        // Older entries were last used, as far as we know, when they were stored
+        if db_conn
+            .prepare(&format!("SELECT accessed_at FROM {table}"))
+            .is_err()
+        {
+            db_conn.execute(
+                &format!("ALTER TABLE {table} ADD COLUMN accessed_at INTEGER NOT NULL DEFAULT 0"),
+                [],
+            )?;
+            db_conn.execute(&format!("UPDATE {table} SET accessed_at = created_at"), [])?;
+        }
+
+        // Likewise for pronunciations, inflections, notes, source HTML, and validators,
+        // which can be missing
+        for column in [
+            "pronunciation",
+            "inflections",
+            "also_called",
+            "usage_notes",
+            "html",
+            "etag",
+            "last_modified",
+        ] {
+            if db_conn
+                .prepare(&format!("SELECT {column} FROM {table}"))
+                .is_err()
+            {
+                db_conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} TEXT"), [])?;
+            }
+        }
+    }
+
+    // Every word looked up, in order, whether or not it was cached
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS history (
+            word        TEXT NOT NULL,
+            mode        TEXT NOT NULL,
+            created_at  INTEGER NOT NULL
+        )",
+        [],
+    )?;
+
+    // Words we looked for and didn't find, in either mode
+    db_conn.execute(
+        "CREATE TABLE IF NOT EXISTS not_found (
+            word        TEXT NOT NULL,
+            mode        TEXT NOT NULL,
+            created_at  INTEGER NOT NULL,
+            UNIQUE (word, mode)
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+// Each mode and format has its own cache table; plain text keeps the original names
+const DICTIONARY_TABLES: [&str; 3] = ["dictionary", "dictionary_markdown", "dictionary_html"];
+const ETYMOLOGY_TABLES: [&str; 3] = ["etymology", "etymology_markdown", "etymology_html"];
+
+const fn table_name(mode: Mode, format: Format) -> &'static str {
+    let tables = match mode {
+        Mode::Definition => DICTIONARY_TABLES,
+        Mode::Etymology => ETYMOLOGY_TABLES,
+    };
+
+    match format {
+        Format::Plain => tables[0],
+        Format::Markdown => tables[1],
+        Format::Html => tables[2],
+    }
+}
+
+// Function to query db for cached results: content, headword details, and when stored
+pub fn query_db(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+    format: Format,
+) -> Result<(String, Headword, i64), rusqlite::Error> {
+    // Only the table name varies; the word itself is always a bound parameter
+    let table = table_name(mode, format);
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT content, pronunciation, inflections, also_called, usage_notes, created_at
+            FROM {table} WHERE word = ?1"
+    ))?;
+
+    // We're looking for only one row; lists are stored one item per line
+    let entry = stmt.query_row([desired_word], |row| {
+        let lines = |column| {
+            row.get::<_, Option<String>>(column).map(|list| {
+                list.map(|list| list.lines().map(str::to_owned).collect())
+                    .unwrap_or_default()
+            })
+        };
+
+        let headword = Headword {
+            pronunciation: row.get(1)?,
+            inflections: lines(2)?,
+            also_called: lines(3)?,
+            usage_notes: lines(4)?,
+        };
+
+        Ok((row.get(0)?, headword, row.get(5)?))
+    })?;
+
+    // Note the use, for eviction; this can fail silently
+    let _touch = db_conn.execute(
+        &format!("UPDATE {table} SET accessed_at = ?1 WHERE word = ?2"),
+        params![unix_now(), desired_word],
+    );
+
+    Ok(entry)
+}
+
+// Function to find the HTML an entry was rendered from, in whichever format it was cached
+// Returns that with the entry's headword details and when it was stored
+pub fn query_html(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+) -> Option<(String, Headword, i64)> {
+    cache_kinds()
+        .filter(|&(kind, _)| kind == mode)
+        .find_map(|(_, format)| {
+            let html: String = db_conn
+                .query_row(
+                    &format!(
+                        "SELECT html FROM {} WHERE word = ?1 AND html IS NOT NULL",
+                        table_name(mode, format)
+                    ),
+                    [desired_word],
+                    |row| row.get(0),
+                )
+                .ok()?;
+            let (_, headword, created_at) = query_db(db_conn, desired_word, mode, format).ok()?;
+
+            Some((html, headword, created_at))
+        })
+}
+
+// Function to keep the compiled HTML alongside a cached entry, so it can be rendered again
+pub fn store_html(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+    format: Format,
+    html: &str,
+) -> Result<(), rusqlite::Error> {
+    let table = table_name(mode, format);
+
+    retry_busy(|| {
+        db_conn.execute(
+            &format!("UPDATE {table} SET html = ?1 WHERE word = ?2"),
+            params![html, desired_word],
+        )
+    })?;
+
+    Ok(())
+}
+
+// Function to find what the source site said identified a cached entry's page, if anything
+pub fn query_validators(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+    format: Format,
+) -> Option<Validators> {
+    let validators = db_conn
+        .query_row(
+            &format!(
+                "SELECT etag, last_modified FROM {} WHERE word = ?1",
+                table_name(mode, format)
+            ),
+            [desired_word],
+            |row| {
+                Ok(Validators {
+                    etag: row.get(0)?,
+                    last_modified: row.get(1)?,
+                })
+            },
+        )
+        .ok()?;
+
+    Some(validators).filter(|validators| !validators.is_empty())
+}
+
+// Function to keep a page's validators with its cached entry, for a conditional request later
+pub fn store_validators(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+    format: Format,
+    validators: &Validators,
+) -> Result<(), rusqlite::Error> {
+    let table = table_name(mode, format);
+
+    retry_busy(|| {
+        db_conn.execute(
+            &format!("UPDATE {table} SET etag = ?1, last_modified = ?2 WHERE word = ?3"),
+            params![validators.etag, validators.last_modified, desired_word],
+        )
+    })?;
+
+    Ok(())
+}
+
+// Function to restart a cached entry's clock, when the site says it's still current
+pub fn refresh_cached(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+    format: Format,
+) -> Result<(), rusqlite::Error> {
+    let table = table_name(mode, format);
+
+    retry_busy(|| {
+        db_conn.execute(
+            &format!("UPDATE {table} SET created_at = ?1, accessed_at = ?1 WHERE word = ?2"),
+            params![unix_now(), desired_word],
+        )
+    })?;
+
+    Ok(())
+}
+
+// Function to find a cached word that differs from this one only in its accents
+// Accents can't be folded in SQL, so this goes through the words in Rust
+pub fn loose_match(db_conn: &Connection, key: &str, mode: Mode, format: Format) -> Option<String> {
+    let table = table_name(mode, format);
+    let folded = loose_key(key);
+
+    let mut stmt = db_conn.prepare(&format!("SELECT word FROM {table}")).ok()?;
+    let words: Vec<String> = stmt
+        .query_map([], |row| row.get(0))
+        .ok()?
+        .filter_map(Result::ok)
+        .collect();
+
+    words.into_iter().find(|word| loose_key(word) == folded)
+}
+
+// Function to try to update cache with new results
+pub fn update_cache(
+    cache_hit: bool,
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+    format: Format,
+    final_output: &str,
+    headword: &Headword,
+) -> Result<(), rusqlite::Error> {
+    let now = unix_now();
+    let table = table_name(mode, format);
+
+    let pronunciation = headword.pronunciation.as_deref();
+    let lines = |list: &[String]| Some(list.join("\n")).filter(|list| !list.is_empty());
+    let inflections = lines(&headword.inflections);
+    let also_called = lines(&headword.also_called);
+    let usage_notes = lines(&headword.usage_notes);
+
+    // If we got a cache hit (forced or expired), update
+    if cache_hit {
+        retry_busy(|| {
+            db_conn.execute(
+                &format!(
+                    "UPDATE {table} SET content = (?1), created_at = (?2), accessed_at = (?2),
+                        pronunciation = (?3), inflections = (?4), also_called = (?5),
+                        usage_notes = (?6) WHERE word = (?7)"
+                ),
+                params![
+                    final_output,
+                    now,
+                    pronunciation,
+                    inflections,
+                    also_called,
+                    usage_notes,
+                    desired_word
+                ],
+            )
+        })?;
+    // Else insert
+    } else {
+        retry_busy(|| {
+            db_conn.execute(
+                &format!(
+                    "INSERT INTO {table}
+                        (word, content, created_at, accessed_at, pronunciation, inflections,
+                            also_called, usage_notes)
+                        VALUES (?1, ?2, ?3, ?3, ?4, ?5, ?6, ?7)"
+                ),
+                params![
+                    desired_word,
+                    final_output,
+                    now,
+                    pronunciation,
+                    inflections,
+                    also_called,
+                    usage_notes
+                ],
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+// Function to trim the cache to size, dropping the least recently used entries
+// Every table counts toward the limit; returns how many entries were dropped
+pub fn evict_cache(db_conn: &Connection, max_entries: usize) -> Result<usize, rusqlite::Error> {
+    let all_entries = DICTIONARY_TABLES
+        .into_iter()
+        .chain(ETYMOLOGY_TABLES)
+        .map(|table| {
+            format!("SELECT '{table}' AS tbl, rowid AS id, accessed_at, created_at FROM {table}")
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+
+    let total: usize = db_conn.query_row(
+        &format!("SELECT COUNT(*) FROM ({all_entries})"),
+        [],
+        |row| row.get(0),
+    )?;
+
+    if total <= max_entries {
+        return Ok(0);
+    }
+
+    let mut stmt = db_conn.prepare(&format!(
+        "SELECT tbl, id FROM ({all_entries}) ORDER BY accessed_at, created_at LIMIT ?1"
+    ))?;
+    let stale = stmt
+        .query_map([total - max_entries], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (table, id) in &stale {
+        retry_busy(|| db_conn.execute(&format!("DELETE FROM {table} WHERE rowid = ?1"), [id]))?;
+    }
+
+    Ok(stale.len())
+}
+
+// Function to check when (if ever) a word was last not found
+pub fn query_not_found(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+) -> Result<i64, rusqlite::Error> {
+    db_conn.query_row(
+        "SELECT created_at FROM not_found WHERE word = ?1 AND mode = ?2",
+        params![desired_word, mode.name()],
+        |row| row.get(0),
+    )
+}
+
+// Function to remember that a word was not found (replacing any older record)
+pub fn record_not_found(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+) -> Result<(), rusqlite::Error> {
+    retry_busy(|| {
+        db_conn.execute(
+            "INSERT OR REPLACE INTO not_found (word, mode, created_at) VALUES (?1, ?2, ?3)",
+            params![desired_word, mode.name(), unix_now()],
+        )
+    })?;
+
+    Ok(())
+}
+
+// Function to rebuild the cache db without its free pages; returns sizes before and after
+pub fn vacuum_cache(db_path: &Path) -> Result<(u64, u64), anyhow::Error> {
+    if !db_path.exists() {
+        return Err(anyhow!("Cache database not found"));
+    }
+
+    let file_size = || -> Result<u64, anyhow::Error> {
+        Ok(fs::metadata(db_path)
+            .context("Failed to read cache database metadata")?
+            .len())
+    };
+
+    let before = file_size()?;
+
+    let db_conn = open_db(db_path).context("Failed to open cache database")?;
+    db_conn
+        .execute("VACUUM", [])
+        .context("Failed to compact cache database")?;
+    drop(db_conn);
+
+    Ok((before, file_size()?))
+}
+
+// Function to drop a word's cached entries in one mode, saying whether there were any
+pub fn forget_cached(db_path: &Path, word: &str, mode: Mode) -> Result<(), anyhow::Error> {
+    let db_conn = open_db(db_path).context("Failed to open cache database")?;
+    create_tables(&db_conn).context("Failed to prepare cache database")?;
+
+    if forget_word(&db_conn, &cache_key(word), mode)? > 0 {
+        eprintln!("Removed cached {} of \"{word}\"", mode.name());
+    } else {
+        eprintln!("No cached {} of \"{word}\"", mode.name());
+    }
+
+    Ok(())
+}
+
+// Function to delete a word from the cache, in every format; returns entries removed
+// Any record of its not being found goes too (uncounted), so the next lookup starts fresh
+fn forget_word(db_conn: &Connection, key: &str, mode: Mode) -> Result<usize, rusqlite::Error> {
+    let mut removed = 0;
+
+    for format in [Format::Plain, Format::Markdown, Format::Html] {
+        let table = table_name(mode, format);
+        removed += db_conn.execute(&format!("DELETE FROM {table} WHERE word = ?1"), [key])?;
+    }
+
+    db_conn.execute(
+        "DELETE FROM not_found WHERE word = ?1 AND mode = ?2",
+        params![key, mode.name()],
+    )?;
+
+    Ok(removed)
+}
+
+// Function to pick a cached word at random, if there are any
+pub fn random_cached_word(db_conn: &Connection, mode: Mode, format: Format) -> Option<String> {
+    let table = table_name(mode, format);
+
+    db_conn
+        .query_row(
+            &format!("SELECT word FROM {table} ORDER BY RANDOM() LIMIT 1"),
+            [],
+            |row| row.get(0),
+        )
+        .ok()
+}
+
+// Function to note that a word was looked up
+pub fn record_history(
+    db_conn: &Connection,
+    desired_word: &str,
+    mode: Mode,
+) -> Result<(), rusqlite::Error> {
+    retry_busy(|| {
+        db_conn.execute(
+            "INSERT INTO history (word, mode, created_at) VALUES (?1, ?2, ?3)",
+            params![desired_word, mode.name(), unix_now()],
+        )
+    })?;
+
+    Ok(())
+}
+
+// Function to fetch the most recent lookups, oldest first
+fn query_history(
+    db_conn: &Connection,
+    count: usize,
+) -> Result<Vec<(String, String, String)>, rusqlite::Error> {
+    let mut stmt = db_conn.prepare(
+        "SELECT datetime(created_at, 'unixepoch'), mode, word FROM (
+            SELECT rowid, word, mode, created_at FROM history ORDER BY rowid DESC LIMIT ?1
+        ) ORDER BY rowid",
+    )?;
+
+    let rows = stmt.query_map([i64::try_from(count).unwrap_or(i64::MAX)], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?;
+
+    rows.collect()
+}
+
+// Function to print the most recent lookups
+pub fn print_history(db_path: &Path, count: usize) -> Result<(), anyhow::Error> {
+    if !db_path.exists() {
+        return Err(anyhow!("Cache database not found"));
+    }
+
+    let db_conn = open_db(db_path).context("Failed to open cache database")?;
+    create_tables(&db_conn).context("Failed to prepare cache database")?;
+
+    for (timestamp, mode, word) in query_history(&db_conn, count)? {
+        println!("{timestamp} UTC  {mode:<10}  {word}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gloss_word::{build_url, Page, Source};
+    use reqwest::Url;
+    use tempfile::TempDir;
+
+    use crate::tests::test_session;
+    use crate::{look_up_entry, random_word, CacheMatch, Session, NOT_FOUND_TTL, WORD_LIST};
+
+    // A fresh cache in a directory of its own, which lasts as long as the TempDir
+    fn test_db() -> (TempDir, Connection) {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_conn = Connection::open(cache_dir.path().join("entries.sqlite")).unwrap();
+        create_tables(&db_conn).unwrap();
+
+        (cache_dir, db_conn)
+    }
+
+    #[test]
+    fn cache_apostrophe() {
+        let (_cache_dir, db_conn) = test_db();
+
+        let word = "ne'er-do-well";
+        let content = "ne'er-do-well\n\nn.\n\nAn irresponsible person.\n";
+
+        update_cache(
+            false,
+            &db_conn,
+            word,
+            Mode::Definition,
+            Format::Plain,
+            content,
+            &Headword::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            query_db(&db_conn, word, Mode::Definition, Format::Plain)
+                .unwrap()
+                .0,
+            content
+        );
+        assert!(query_db(&db_conn, word, Mode::Etymology, Format::Plain).is_err());
+        assert!(query_db(&db_conn, "' OR '1'='1", Mode::Definition, Format::Plain).is_err());
+    }
+
+    #[test]
+    fn cache_formats() {
+        let (_cache_dir, db_conn) = test_db();
+
+        let markdown = "**at·a·vism**\n";
+        update_cache(
+            false,
+            &db_conn,
+            "atavism",
+            Mode::Definition,
+            Format::Markdown,
+            markdown,
+            &Headword::default(),
+        )
+        .unwrap();
+
+        // Each format is cached separately
+        assert_eq!(
+            query_db(&db_conn, "atavism", Mode::Definition, Format::Markdown)
+                .unwrap()
+                .0,
+            markdown
+        );
+        assert!(query_db(&db_conn, "atavism", Mode::Definition, Format::Plain).is_err());
+        assert!(query_db(&db_conn, "atavism", Mode::Definition, Format::Html).is_err());
+    }
+
+    #[test]
+    fn cache_export_import() {
+        let (cache_dir, db_conn) = test_db();
+        let db_path = cache_dir.path().join("entries.sqlite");
+
+        let body = "at·a·vism\n\nn.\n\n1.  The reappearance, \"so to speak\".\n";
+        update_cache(
+            false,
+            &db_conn,
+            "atavism",
+            Mode::Definition,
+            Format::Plain,
+            body,
+            &Headword {
+                pronunciation: Some("(ăt′ə-vĭz′əm)".to_owned()),
+                inflections: vec!["atavisms".to_owned()],
+                also_called: vec!["throwback".to_owned()],
+                ..Headword::default()
+            },
+        )
+        .unwrap();
+        update_cache(
+            false,
+            &db_conn,
+            "forest",
+            Mode::Etymology,
+            Format::Markdown,
+            "**forest (n.)**\n",
+            &Headword::default(),
+        )
+        .unwrap();
+
+        for (file_name, csv) in [("export.json", false), ("export.csv", true)] {
+            let export_path = cache_dir.path().join(file_name);
+            export_cache(&db_path, &export_path, csv).unwrap();
+
+            // Into a fresh cache, everything is new
+            let new_db_path = cache_dir.path().join(format!("{file_name}.sqlite"));
+            let new_conn = Connection::open(&new_db_path).unwrap();
+            create_tables(&new_conn).unwrap();
+
+            import_cache(&new_db_path, &export_path).unwrap();
+            let (content, headword, _) =
+                query_db(&new_conn, "atavism", Mode::Definition, Format::Plain).unwrap();
+            assert_eq!(content, body);
+            assert_eq!(headword.pronunciation.as_deref(), Some("(ăt′ə-vĭz′əm)"));
+            assert_eq!(headword.inflections, ["atavisms"]);
+            assert_eq!(headword.also_called, ["throwback"]);
+            assert!(headword.usage_notes.is_empty());
+            assert!(query_db(&new_conn, "forest", Mode::Etymology, Format::Markdown).is_ok());
+
+            // A second import changes nothing
+            import_cache(&new_db_path, &export_path).unwrap();
+            let count: i64 = new_conn
+                .query_row("SELECT COUNT(*) FROM dictionary", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, 1);
+        }
+    }
+
+    #[test]
+    fn cache_vacuum() {
+        let (cache_dir, db_conn) = test_db();
+        let db_path = cache_dir.path().join("entries.sqlite");
+
+        // Fill the cache, then empty it, leaving free pages behind
+        let content = "x".repeat(10_000);
+        for i in 0..50 {
+            let word = format!("word{i}");
+            update_cache(
+                false,
+                &db_conn,
+                &word,
+                Mode::Definition,
+                Format::Plain,
+                &content,
+                &Headword::default(),
+            )
+            .unwrap();
+        }
+        db_conn.execute("DELETE FROM dictionary", []).unwrap();
+        drop(db_conn);
+
+        let (before, after) = vacuum_cache(&db_path).unwrap();
+        assert!(after < before);
+
+        assert!(vacuum_cache(&cache_dir.path().join("missing.sqlite")).is_err());
+    }
+
+    #[test]
+    fn cache_forget() {
+        let (_cache_dir, db_conn) = test_db();
+
+        for (mode, format) in [
+            (Mode::Definition, Format::Plain),
+            (Mode::Definition, Format::Html),
+            (Mode::Etymology, Format::Plain),
+        ] {
+            update_cache(
+                false,
+                &db_conn,
+                "forest",
+                mode,
+                format,
+                "forest\n",
+                &Headword::default(),
+            )
+            .unwrap();
+        }
+
+        // Only the given mode is touched, but in every format
+        assert_eq!(
+            forget_word(&db_conn, "forest", Mode::Definition).unwrap(),
+            2
+        );
+        assert!(query_db(&db_conn, "forest", Mode::Definition, Format::Plain).is_err());
+        assert!(query_db(&db_conn, "forest", Mode::Etymology, Format::Plain).is_ok());
+
+        assert_eq!(
+            forget_word(&db_conn, "forest", Mode::Definition).unwrap(),
+            0
+        );
+
+        // A record of a miss is cleared, but it isn't an entry
+        record_not_found(&db_conn, "asdfgh", Mode::Definition).unwrap();
+        assert_eq!(
+            forget_word(&db_conn, "asdfgh", Mode::Definition).unwrap(),
+            0
+        );
+        assert!(query_not_found(&db_conn, "asdfgh", Mode::Definition).is_err());
+    }
+
+    #[test]
+    fn random_pick() {
+        let (_cache_dir, db_conn) = test_db();
+
+        // An empty cache (or none) leaves the built-in list
+        for conn in [None, Some(&db_conn)] {
+            let word = random_word(conn, Mode::Definition, Format::Plain);
+            assert!(WORD_LIST.lines().any(|line| line == word));
+        }
+
+        update_cache(
+            false,
+            &db_conn,
+            "cummerbund",
+            Mode::Etymology,
+            Format::Plain,
+            "cummerbund (n.)\n",
+            &Headword::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            random_word(Some(&db_conn), Mode::Etymology, Format::Plain),
+            "cummerbund"
+        );
+    }
+
+    #[test]
+    fn history_log() {
+        let (_cache_dir, db_conn) = test_db();
+
+        for (word, mode) in [
+            ("atavism", Mode::Definition),
+            ("forest", Mode::Etymology),
+            ("atavism", Mode::Definition),
+        ] {
+            record_history(&db_conn, word, mode).unwrap();
+        }
+
+        // Repeats are kept, and the latest come last
+        let history = query_history(&db_conn, 2).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].1, "etymology");
+        assert_eq!(history[0].2, "forest");
+        assert_eq!(history[1].2, "atavism");
+
+        assert_eq!(query_history(&db_conn, 10).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn cache_busy() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_path = cache_dir.path().join("entries.sqlite");
+
+        let db_conn = open_db(&db_path).unwrap();
+        create_tables(&db_conn).unwrap();
+
+        // Another connection holds the write lock for a moment
+        let mut other_conn = Connection::open(&db_path).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let holder = thread::spawn(move || {
+            let transaction = other_conn
+                .transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)
+                .unwrap();
+            tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(300));
+            transaction.commit().unwrap();
+        });
+
+        // Our write waits its turn, rather than failing
+        rx.recv().unwrap();
+        update_cache(
+            false,
+            &db_conn,
+            "atavism",
+            Mode::Definition,
+            Format::Plain,
+            "at·a·vism\n",
+            &Headword::default(),
+        )
+        .unwrap();
+        holder.join().unwrap();
+
+        assert!(query_db(&db_conn, "atavism", Mode::Definition, Format::Plain).is_ok());
+    }
+
+    #[test]
+    fn cache_wal() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_path = cache_dir.path().join("entries.sqlite");
+
+        let db_conn = open_db(&db_path).unwrap();
+        create_tables(&db_conn).unwrap();
+
+        let journal_mode: String = db_conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // The extra files live alongside the db, in the cache directory
+        assert!(cache_dir.path().join("entries.sqlite-wal").exists());
+        assert!(cache_dir.path().join("entries.sqlite-shm").exists());
+    }
+
+    #[test]
+    fn cache_punctuation() {
+        let (_cache_dir, db_conn) = test_db();
+
+        // Quotes are bound as parameters, never spliced into SQL
+        for word in ["o'clock", "jack-o'-lantern", "mother-in-law"] {
+            update_cache(
+                false,
+                &db_conn,
+                &cache_key(word),
+                Mode::Definition,
+                Format::Plain,
+                word,
+                &Headword::default(),
+            )
+            .unwrap();
+        }
+
+        // However the apostrophe was typed, it's the same entry
+        let (content, _, _) = query_db(
+            &db_conn,
+            &cache_key("O\u{2019}Clock"),
+            Mode::Definition,
+            Format::Plain,
+        )
+        .unwrap();
+        assert_eq!(content, "o'clock");
+        assert!(query_db(&db_conn, "mother-in-law", Mode::Definition, Format::Plain).is_ok());
+    }
+
+    #[test]
+    fn cache_html() {
+        let (_cache_dir, db_conn) = test_db();
+
+        let html = "<h2>isth·mus</h2><div>A narrow strip of land.</div>";
+        let headword = Headword {
+            pronunciation: Some("(ĭs′məs)".to_owned()),
+            ..Headword::default()
+        };
+
+        // Nothing to render yet
+        assert!(query_html(&db_conn, "isthmus", Mode::Definition).is_none());
+
+        update_cache(
+            false,
+            &db_conn,
+            "isthmus",
+            Mode::Definition,
+            Format::Plain,
+            "isth·mus\n\nA narrow strip of land.\n",
+            &headword,
+        )
+        .unwrap();
+
+        // An entry from before HTML was kept can't be rendered anew
+        assert!(query_html(&db_conn, "isthmus", Mode::Definition).is_none());
+
+        store_html(&db_conn, "isthmus", Mode::Definition, Format::Plain, html).unwrap();
+
+        // Any format can now be had from the plain-text entry's HTML, but not etymology
+        let (cached, cached_headword, _) =
+            query_html(&db_conn, "isthmus", Mode::Definition).unwrap();
+        assert_eq!(cached, html);
+        assert_eq!(cached_headword, headword);
+        assert!(query_html(&db_conn, "isthmus", Mode::Etymology).is_none());
+        assert!(query_db(&db_conn, "isthmus", Mode::Definition, Format::Markdown).is_err());
+    }
+
+    #[test]
+    fn cache_validators() {
+        let (_cache_dir, db_conn) = test_db();
+
+        update_cache(
+            false,
+            &db_conn,
+            "forest",
+            Mode::Etymology,
+            Format::Plain,
+            "forest (n.)\n",
+            &Headword::default(),
+        )
+        .unwrap();
+
+        // The site may not have said anything, in which case there's nothing to send back
+        assert!(query_validators(&db_conn, "forest", Mode::Etymology, Format::Plain).is_none());
+        store_validators(
+            &db_conn,
+            "forest",
+            Mode::Etymology,
+            Format::Plain,
+            &Validators::default(),
+        )
+        .unwrap();
+        assert!(query_validators(&db_conn, "forest", Mode::Etymology, Format::Plain).is_none());
+
+        let validators = Validators {
+            etag: Some(r#"W/"1a2b""#.to_owned()),
+            last_modified: None,
+        };
+        store_validators(
+            &db_conn,
+            "forest",
+            Mode::Etymology,
+            Format::Plain,
+            &validators,
+        )
+        .unwrap();
+        assert_eq!(
+            query_validators(&db_conn, "forest", Mode::Etymology, Format::Plain),
+            Some(validators)
+        );
+        assert!(query_validators(&db_conn, "forest", Mode::Etymology, Format::Html).is_none());
+
+        // An unchanged page makes an old entry new again
+        db_conn
+            .execute("UPDATE etymology SET created_at = 0", [])
+            .unwrap();
+        refresh_cached(&db_conn, "forest", Mode::Etymology, Format::Plain).unwrap();
+        let (_, _, created_at) =
+            query_db(&db_conn, "forest", Mode::Etymology, Format::Plain).unwrap();
+        assert!(unix_now() - created_at < 60);
+    }
+
+    #[test]
+    fn cache_eviction() {
+        let (_cache_dir, db_conn) = test_db();
+
+        for (word, mode) in [
+            ("atavism", Mode::Definition),
+            ("isthmus", Mode::Definition),
+            ("forest", Mode::Etymology),
+        ] {
+            update_cache(
+                false,
+                &db_conn,
+                word,
+                mode,
+                Format::Plain,
+                "x",
+                &Headword::default(),
+            )
+            .unwrap();
+        }
+
+        // Pretend everything is old, then use one entry again
+        for table in ["dictionary", "etymology"] {
+            db_conn
+                .execute(&format!("UPDATE {table} SET accessed_at = 0"), [])
+                .unwrap();
+        }
+        db_conn
+            .execute(
+                "UPDATE dictionary SET accessed_at = 1 WHERE word = 'isthmus'",
+                [],
+            )
+            .unwrap();
+        query_db(&db_conn, "atavism", Mode::Definition, Format::Plain).unwrap();
+
+        assert_eq!(evict_cache(&db_conn, 3).unwrap(), 0);
+        assert_eq!(evict_cache(&db_conn, 2).unwrap(), 1);
+        assert!(query_db(&db_conn, "forest", Mode::Etymology, Format::Plain).is_err());
+        assert!(query_db(&db_conn, "atavism", Mode::Definition, Format::Plain).is_ok());
+
+        assert_eq!(evict_cache(&db_conn, 1).unwrap(), 1);
+        assert!(query_db(&db_conn, "isthmus", Mode::Definition, Format::Plain).is_err());
+    }
+
+    #[test]
+    fn cache_misses() {
+        let (_cache_dir, db_conn) = test_db();
+
+        assert!(query_not_found(&db_conn, "zzxq", Mode::Definition).is_err());
+
+        record_not_found(&db_conn, "zzxq", Mode::Definition).unwrap();
+        let missed_at = query_not_found(&db_conn, "zzxq", Mode::Definition).unwrap();
+        assert!(unix_now() - missed_at < NOT_FOUND_TTL);
+
+        // Misses are kept separately by mode, and recording twice is fine
+        assert!(query_not_found(&db_conn, "zzxq", Mode::Etymology).is_err());
+        record_not_found(&db_conn, "zzxq", Mode::Definition).unwrap();
+    }
+
+    #[test]
+    fn cache_timestamps() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_path = cache_dir.path().join("entries.sqlite");
+
+        // Simulate a table from before timestamps were added
+        let db_conn = Connection::open(&db_path).unwrap();
+        db_conn
+            .execute(
+                "CREATE TABLE etymology (word TEXT UNIQUE NOT NULL, content TEXT NOT NULL)",
+                [],
+            )
+            .unwrap();
+        db_conn
+            .execute(
+                "INSERT INTO etymology (word, content) VALUES ('old', 'x')",
+                [],
+            )
+            .unwrap();
+
+        create_tables(&db_conn).unwrap();
+        let (_, headword, migrated_at) =
+            query_db(&db_conn, "old", Mode::Etymology, Format::Plain).unwrap();
+        assert_eq!(headword, Headword::default());
+        assert!(unix_now() - migrated_at < 60);
+
+        // Refreshing an entry should bump its timestamp
+        db_conn
+            .execute("UPDATE etymology SET created_at = 0", [])
+            .unwrap();
+        update_cache(
+            true,
+            &db_conn,
+            "old",
+            Mode::Etymology,
+            Format::Plain,
+            "y",
+            &Headword::default(),
+        )
+        .unwrap();
+
+        let (content, _, refreshed_at) =
+            query_db(&db_conn, "old", Mode::Etymology, Format::Plain).unwrap();
+        assert_eq!(content, "y");
+        assert!(refreshed_at > 0);
+    }
+
+    #[test]
+    fn corrupt_cache() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_path = cache_dir.path().join("entries.sqlite");
+        fs::write(&db_path, "this is not a database, just some garbage bytes").unwrap();
+
+        let db_conn = open_cache(&db_path).unwrap();
+        update_cache(
+            false,
+            &db_conn,
+            "forest",
+            Mode::Definition,
+            Format::Html,
+            "<p>woods</p>",
+            &Headword::default(),
+        )
+        .unwrap();
+
+        let bad_path = cache_dir.path().join("entries.sqlite.bad");
+        assert!(fs::read_to_string(bad_path).unwrap().contains("garbage"));
+        assert!(query_db(&db_conn, "forest", Mode::Definition, Format::Html).is_ok());
+    }
+
+    #[test]
+    fn loose_cache() {
+        let db_conn = Connection::open_in_memory().unwrap();
+        create_tables(&db_conn).unwrap();
+
+        update_cache(
+            false,
+            &db_conn,
+            "resume",
+            Mode::Definition,
+            Format::Plain,
+            "To begin again",
+            &Headword::default(),
+        )
+        .unwrap();
+
+        let key = cache_key("R\u{e9}sum\u{e9}");
+        assert!(query_db(&db_conn, &key, Mode::Definition, Format::Plain).is_err());
+        assert_eq!(
+            loose_match(&db_conn, &key, Mode::Definition, Format::Plain).as_deref(),
+            Some("resume")
+        );
+        assert!(loose_match(&db_conn, &key, Mode::Etymology, Format::Plain).is_none());
+        assert!(loose_match(&db_conn, "result", Mode::Definition, Format::Plain).is_none());
+    }
+
+    #[test]
+    fn loose_refresh() {
+        let db_conn = Connection::open_in_memory().unwrap();
+        create_tables(&db_conn).unwrap();
+
+        update_cache(
+            false,
+            &db_conn,
+            "resume",
+            Mode::Definition,
+            Format::Html,
+            "<h2>re·sume</h2>\n",
+            &Headword::default(),
+        )
+        .unwrap();
+        db_conn
+            .execute("UPDATE dictionary_html SET created_at = 0", [])
+            .unwrap();
+
+        // The accented word's page, as if fetched ahead of time
+        let url = build_url(Source::Tfd, "r\u{e9}sum\u{e9}");
+        let page = Page {
+            text: r#"<div id="Definition"><section data-src="hm"><h2>ré·su·mé</h2><div class="pseg"><i>n.</i></div></section></div>"#.to_owned(),
+            url: Url::parse(&url).unwrap(),
+            validators: Validators::default(),
+        };
+
+        let session = Session {
+            cache_match: CacheMatch::Loose,
+            format: Format::Html,
+            max_age: 60,
+            ..test_session(Some(db_conn))
+        };
+        session.prefetched.borrow_mut().insert(url, Ok(page));
+        look_up_entry(&session, Source::Tfd, "r\u{e9}sum\u{e9}", true).unwrap();
+
+        // The expired entry that loosely matched belongs to another word, so it's left alone
+        let db_conn = session.db_conn.as_ref().unwrap();
+        let (entry, _, created_at) =
+            query_db(db_conn, "resume", Mode::Definition, Format::Html).unwrap();
+        assert_eq!(entry, "<h2>re·sume</h2>\n");
+        assert_eq!(created_at, 0);
+
+        // The word looked up gets an entry of its own
+        let (entry, _, _) = query_db(
+            db_conn,
+            &cache_key("r\u{e9}sum\u{e9}"),
+            Mode::Definition,
+            Format::Html,
+        )
+        .unwrap();
+        assert!(entry.contains("ré·su·mé"));
+    }
+
+    #[test]
+    fn integrity_check() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_path = cache_dir.path().join("entries.sqlite");
+        assert!(check_cache_db(&db_path).is_ok());
+
+        fs::write(&db_path, "this is not a database").unwrap();
+        assert!(check_cache_db(&db_path).is_err());
+    }
+}
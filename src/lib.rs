@@ -1,203 +1,2303 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
 
-use std::io::Write;
-use std::process::Command;
-use std::str; // For str::from_utf8
+use core::time::Duration;
+use std::io;
+use std::path::PathBuf;
+use std::str; // For str::Utf8Error
 
-use anyhow::Context;
-use regex::Regex;
-use scraper::{ElementRef, Html, Selector};
-use tempfile::NamedTempFile;
+use owo_colors::OwoColorize;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::{Captures, Regex};
+use reqwest::blocking::Client;
+use reqwest::header::{
+    HeaderValue, InvalidHeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    RETRY_AFTER,
+};
+use reqwest::{NoProxy, Proxy, StatusCode, Url};
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::Deserialize;
+use thiserror::Error;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+// Our own conversion to plain text, which a build can leave out (the "render" feature)
+#[cfg(feature = "render")]
+mod render;
+#[cfg(feature = "render")]
+pub use render::render_plain;
+
+// Without either renderer, there would be no way to turn an entry into text
+#[cfg(not(any(feature = "pandoc", feature = "render")))]
+compile_error!("Enable the \"pandoc\" feature, the \"render\" feature, or both");
+
+// Everything that calls out to Pandoc, which a build can leave out (the "pandoc" feature)
+#[cfg(feature = "pandoc")]
+mod pandoc;
+#[cfg(feature = "pandoc")]
+pub use pandoc::{
+    pandoc_command, pandoc_plain, pandoc_primary, remove_scratch_dir, render_html_to_text,
+    run_pandoc, set_lossy_output, set_pandoc_path,
+};
+
+// A stand-in source site, shared with the binary's tests
+#[cfg(test)]
+mod test_server;
+
+// Identify ourselves politely to the source sites
+pub const USER_AGENT: &str = concat!(
+    "gloss-word/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/theodore-s-beers/gloss-word)"
+);
+
+// Characters left alone when a word goes into a URL path (as in RFC 3986)
+const PATH_UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// Kinds of entry we can look up
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Definition,
+    Etymology,
+}
+
+impl Mode {
+    #[must_use]
+    // Lowercase name, for messages and JSON output
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Definition => "definition",
+            Self::Etymology => "etymology",
+        }
+    }
+}
+
+// What final output should look like
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Plain,
+    Markdown,
+    // The scraped HTML, as is
+    Html,
+}
+
+impl Format {
+    #[must_use]
+    // Lowercase name, as on the command line
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Plain => "plain",
+            Self::Markdown => "markdown",
+            Self::Html => "html",
+        }
+    }
+}
+
+// What turns scraped HTML into text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    Pandoc,
+    // Our own, rougher conversion, for when Pandoc isn't available (the "render" feature)
+    Builtin,
+}
+
+impl Default for Renderer {
+    // Pandoc, unless we were built without it
+    fn default() -> Self {
+        if cfg!(feature = "pandoc") {
+            Self::Pandoc
+        } else {
+            Self::Builtin
+        }
+    }
+}
+
+// Which of a page's entries to keep; TFD sometimes has several
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sections {
+    First,
+    All,
+}
+
+impl Sections {
+    // How many of the sections found should be used?
+    const fn count(self, found: usize) -> usize {
+        match self {
+            Self::First if found > 0 => 1,
+            Self::First | Self::All => found,
+        }
+    }
+}
+
+// Set between TFD entries, as TFD itself does within one
+const SECTION_SEPARATOR: &str = r#"<hr class="hmsep">"#;
+
+// Which parts of a definition to keep, if not all of it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SenseFilter {
+    // Part of speech, either spelled out ("verb") or abbreviated as on TFD ("v.")
+    pub pos: Option<String>,
+    // Sense number, counted within each part of speech
+    pub sense: Option<usize>,
+}
+
+impl SenseFilter {
+    #[must_use]
+    // Is there anything to filter by?
+    pub const fn is_empty(&self) -> bool {
+        self.pos.is_none() && self.sense.is_none()
+    }
+
+    // Does a TFD label like "tr.v." name the requested part of speech?
+    fn matches_pos(&self, label: &str) -> bool {
+        let Some(pos) = &self.pos else {
+            return true;
+        };
+
+        let wanted = pos.trim().trim_end_matches('.').to_lowercase();
+        let abbreviation = match wanted.as_str() {
+            "noun" => "n",
+            "verb" => "v",
+            "adjective" => "adj",
+            "adverb" => "adv",
+            "pronoun" => "pron",
+            "preposition" => "prep",
+            "conjunction" => "conj",
+            "interjection" => "interj",
+            other => other,
+        };
+
+        label
+            .split('.')
+            .any(|part| part.eq_ignore_ascii_case(abbreviation))
+    }
+}
+
+// Sites we know how to scrape
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    // The Free Dictionary (American Heritage), for definitions
+    Tfd,
+    // Online Etymology Dictionary, for etymologies
+    Etymonline,
+    // Wiktionary, for etymologies that Etymonline lacks
+    Wiktionary,
+}
+
+impl Source {
+    #[must_use]
+    // The site we use for a given mode, unless told otherwise
+    pub const fn default_for(mode: Mode) -> Self {
+        match mode {
+            Mode::Definition => Self::Tfd,
+            Mode::Etymology => Self::Etymonline,
+        }
+    }
+
+    #[must_use]
+    // What kind of entry does this site give?
+    pub const fn mode(self) -> Mode {
+        match self {
+            Self::Tfd => Mode::Definition,
+            Self::Etymonline | Self::Wiktionary => Mode::Etymology,
+        }
+    }
+
+    #[must_use]
+    // Human-readable name, for messages
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Tfd => "The Free Dictionary",
+            Self::Etymonline => "Etymonline",
+            Self::Wiktionary => "Wiktionary",
+        }
+    }
+}
+
+// The dictionaries that TFD brings together; we show American Heritage's entry by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dictionary {
+    #[default]
+    AmericanHeritage,
+    Collins,
+    RandomHouse,
+}
+
+impl Dictionary {
+    #[must_use]
+    // The data-src attribute of this dictionary's sections on a TFD page
+    pub const fn data_src(self) -> &'static str {
+        match self {
+            Self::AmericanHeritage => "hm",
+            Self::Collins => "hc_dict",
+            Self::RandomHouse => "rHouse",
+        }
+    }
+
+    #[must_use]
+    // Human-readable name, for messages
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::AmericanHeritage => "American Heritage",
+            Self::Collins => "Collins",
+            Self::RandomHouse => "Random House Kernerman Webster's",
+        }
+    }
+}
+
+// Spelling conventions, for when a word isn't found as typed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Us,
+    Uk,
+}
+
+// Pairs of (US, UK) spellings; add to either list as needed
+// Whole words are checked first, then endings (in order, so longer ones go first)
+const SPELLINGS: &[(&str, &str)] = &[
+    ("aluminum", "aluminium"),
+    ("analog", "analogue"),
+    ("armor", "armour"),
+    ("behavior", "behaviour"),
+    ("canceled", "cancelled"),
+    ("catalog", "catalogue"),
+    ("center", "centre"),
+    ("color", "colour"),
+    ("cozy", "cosy"),
+    ("defense", "defence"),
+    ("dialog", "dialogue"),
+    ("favor", "favour"),
+    ("favorite", "favourite"),
+    ("fiber", "fibre"),
+    ("flavor", "flavour"),
+    ("gray", "grey"),
+    ("harbor", "harbour"),
+    ("honor", "honour"),
+    ("humor", "humour"),
+    ("jewelry", "jewellery"),
+    ("labor", "labour"),
+    ("license", "licence"),
+    ("liter", "litre"),
+    ("maneuver", "manoeuvre"),
+    ("meter", "metre"),
+    ("mold", "mould"),
+    ("mustache", "moustache"),
+    ("neighbor", "neighbour"),
+    ("odor", "odour"),
+    ("offense", "offence"),
+    ("pajamas", "pyjamas"),
+    ("plow", "plough"),
+    ("program", "programme"),
+    ("rumor", "rumour"),
+    ("skeptic", "sceptic"),
+    ("theater", "theatre"),
+    ("tire", "tyre"),
+    ("traveler", "traveller"),
+    ("traveling", "travelling"),
+    ("vapor", "vapour"),
+    ("vigor", "vigour"),
+];
+
+const ENDINGS: &[(&str, &str)] = &[
+    ("izations", "isations"),
+    ("ization", "isation"),
+    ("izing", "ising"),
+    ("ized", "ised"),
+    ("izes", "ises"),
+    ("ize", "ise"),
+    ("yzing", "ysing"),
+    ("yzed", "ysed"),
+    ("yzes", "yses"),
+    ("yze", "yse"),
+];
+
+impl Region {
+    #[must_use]
+    // This region's spelling of a word (or phrase), if it differs from the one given
+    // E.g., "color" becomes "colour" for the UK, and "analyse" becomes "analyze" for the US
+    pub fn respell(self, word: &str) -> Option<String> {
+        let respelled = word
+            .split(' ')
+            .map(|part| self.respell_part(part))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        (respelled != word).then_some(respelled)
+    }
+
+    fn respell_part(self, word: &str) -> String {
+        let direction = |&(us, uk): &(&'static str, &'static str)| match self {
+            Self::Us => (uk, us),
+            Self::Uk => (us, uk),
+        };
+
+        if let Some((_, to)) = SPELLINGS
+            .iter()
+            .map(direction)
+            .find(|(from, _)| *from == word)
+        {
+            return to.to_owned();
+        }
+
+        // A short stem is likely a word of its own ("size", "rise")
+        for (from, to) in ENDINGS.iter().map(direction) {
+            if let Some(stem) = word
+                .strip_suffix(from)
+                .filter(|stem| stem.chars().count() > 2)
+            {
+                return format!("{stem}{to}");
+            }
+        }
+
+        word.to_owned()
+    }
+}
+
+// Everything that can go wrong in a lookup
+#[derive(Debug, Error)]
+pub enum GlossError {
+    #[error("Entry not found")]
+    NotFound,
+    // Only ever the answer to a conditional request (see get_page)
+    #[error("Page unchanged since it was cached")]
+    NotModified,
+    #[error("Request timed out")]
+    Timeout(#[source] reqwest::Error),
+    #[error("{context}")]
+    Http {
+        context: &'static str,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Invalid proxy URL: {url}")]
+    Proxy {
+        url: String,
+        #[source]
+        source: reqwest::Error,
+    },
+    #[error("Invalid user agent: {agent:?}")]
+    UserAgent {
+        agent: String,
+        #[source]
+        source: InvalidHeaderValue,
+    },
+    #[error("Source site returned HTTP {0}")]
+    Status(StatusCode),
+    // With the seconds to wait, if the site said (in Retry-After)
+    #[error("Rate limited by source site; slow down{}", retry_after_note(*.0))]
+    RateLimited(Option<u64>),
+    #[error("Pandoc not found in PATH; install it from https://pandoc.org/installing.html")]
+    PandocMissing,
+    #[error("Pandoc path {} {reason}", .path.display())]
+    PandocPath { path: PathBuf, reason: &'static str },
+    #[error("Pandoc failed: {0}")]
+    PandocFailed(String),
+    #[error("Failed to convert Pandoc output to string (try --lossy)")]
+    PandocOutput(#[from] str::Utf8Error),
+    #[error("{context}")]
+    Io {
+        context: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    #[cfg(feature = "cache")]
+    #[error("Cache operation failed")]
+    Cache(#[from] rusqlite::Error),
+    #[error("No matching sense; available: {0}")]
+    NoSuchSense(String),
+}
+
+// The end of the rate-limit message, saying how long to wait if we know
+fn retry_after_note(seconds: Option<u64>) -> String {
+    seconds.map_or_else(String::new, |seconds| {
+        format!(" (try again in {seconds} seconds)")
+    })
+}
+
+impl GlossError {
+    // Give timeouts their own variant; otherwise use the context provided
+    fn http(source: reqwest::Error, context: &'static str) -> Self {
+        if source.is_timeout() {
+            Self::Timeout(source)
+        } else {
+            Self::Http { context, source }
+        }
+    }
+
+    #[cfg(feature = "pandoc")]
+    const fn io(source: io::Error, context: &'static str) -> Self {
+        Self::Io { context, source }
+    }
+
+    #[must_use]
+    // Is this worth retrying? (Dropped connection, rate limit, or 5xx)
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Http { source, .. } => source.is_connect() || source.is_body(),
+            Self::Status(status) => status.is_server_error(),
+            Self::RateLimited(_) => true,
+            _ => false,
+        }
+    }
+
+    #[must_use]
+    // How long the source site asked us to wait before trying again, if it did
+    pub const fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::RateLimited(Some(seconds)) => Some(Duration::from_secs(*seconds)),
+            _ => None,
+        }
+    }
+}
+
+#[must_use]
+// Lowercase a word or phrase, trimming it and collapsing any runs of whitespace
+// Curly apostrophes and Unicode hyphens or minus signs (as from autocorrect) become ASCII
+// En and em dashes are left alone, since they don't join the parts of a word
+// The result serves both for building URLs and as the cache key
+pub fn normalize_word(input: &str) -> String {
+    input
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .replace(['\u{2018}', '\u{2019}', '\u{2bc}'], "'")
+        .replace(
+            [
+                '\u{2010}', '\u{2011}', '\u{2012}', '\u{2212}', '\u{fe63}', '\u{ff0d}',
+            ],
+            "-",
+        )
+}
+
+#[must_use]
+// The form of a word under which its entry is cached
+// Unicode NFC means that composed and decomposed accents (é vs. e + ´) collide
+// Words that differ in their letters, like "café" and "cafe", are still distinct
+pub fn cache_key(word: &str) -> String {
+    normalize_word(word).nfc().collect()
+}
+
+#[must_use]
+// A looser form of the cache key, under which accent variants ("résumé", "resume") match
+// Letters are decomposed (NFD), and their combining marks dropped
+pub fn loose_key(word: &str) -> String {
+    normalize_word(word)
+        .nfd()
+        .filter(|&c| !is_combining_mark(c))
+        .collect()
+}
+
+#[must_use]
+// Build the URL of the source page for a word
+pub fn build_url(source: Source, word: &str) -> String {
+    let encoded = utf8_percent_encode(word, PATH_UNRESERVED).to_string();
+
+    match source {
+        // TFD prefers plus signs between the words of a phrase
+        Source::Tfd => {
+            "https://www.thefreedictionary.com/".to_owned() + &encoded.replace("%20", "+")
+        }
+        Source::Etymonline => "https://www.etymonline.com/word/".to_owned() + &encoded,
+        // Wiktionary titles use underscores for spaces
+        Source::Wiktionary => {
+            "https://en.wiktionary.org/wiki/".to_owned() + &encoded.replace("%20", "_")
+        }
+    }
+}
+
+#[must_use]
+// Build the URL of a site's search page, if suggestions live on a separate one
+pub fn build_search_url(source: Source, word: &str) -> Option<String> {
+    let encoded = utf8_percent_encode(word, PATH_UNRESERVED).to_string();
+
+    match source {
+        // TFD shows suggestions on the entry page itself; Wiktionary has none to speak of
+        Source::Tfd | Source::Wiktionary => None,
+        Source::Etymonline => Some("https://www.etymonline.com/search?q=".to_owned() + &encoded),
+    }
+}
+
+#[must_use]
+// The page on which each site features its word of the day
+pub const fn build_wotd_url(source: Source) -> &'static str {
+    match source {
+        Source::Tfd => "https://www.thefreedictionary.com/",
+        Source::Etymonline => "https://www.etymonline.com/",
+        Source::Wiktionary => "https://en.wiktionary.org/wiki/Wiktionary:Word_of_the_day",
+    }
+}
+
+#[must_use]
+// Remove the headword (e.g. "at·a·vism") from the top of each entry in a definition
+// Plain text and Markdown have it as the first paragraph, after any separator
+pub fn strip_headword(source: Source, format: Format, output: &str) -> String {
+    if source.mode() == Mode::Etymology {
+        return output.to_owned();
+    }
+
+    let re_headword = match format {
+        Format::Html => Regex::new(r"(?s)<h2\b[^>]*>.*?</h2>\s*").unwrap(),
+        Format::Plain | Format::Markdown => {
+            Regex::new(r"(?P<pre>\A|\n-{3,}\n\n)[^\n]+\n\n").unwrap()
+        }
+    };
+
+    re_headword.replace_all(output, "$pre").into_owned()
+}
+
+#[must_use]
+// Remove usage examples from compiled results, for terser definitions
+// TFD sets them in span.illustration (with no spans inside), after a colon and between semicolons
+pub fn strip_examples(source: Source, results: &str) -> String {
+    if source.mode() == Mode::Etymology {
+        return results.to_owned();
+    }
+
+    let re_example = Regex::new(r#"(?s)\s*[:;]?\s*<span class="illustration">.*?</span>"#).unwrap();
+
+    re_example.replace_all(results, "").into_owned()
+}
+
+#[must_use]
+// Point relative links (like Etymonline's cross-references) at the source site,
+// so that they still lead somewhere once converted
+pub fn absolutize_links(source: Source, results: &str) -> String {
+    let re_href = Regex::new(r#"href="/(?P<path>[^/"][^"]*)""#).unwrap();
+    let root = build_wotd_url(source);
+
+    re_href
+        .replace_all(results, |caps: &Captures| {
+            format!(r#"href="{root}{}""#, &caps["path"])
+        })
+        .into_owned()
+}
+
+#[must_use]
+// Highlight headwords, part-of-speech labels, and sense numbers in final output
+// This runs on the plain text, so cached entries can be colored too
+pub fn colorize(source: Source, output: &str) -> String {
+    if source.mode() == Mode::Etymology {
+        // Headwords are one-line paragraphs like "forest (n.)"
+        let re_headword =
+            Regex::new(r"(?m)(?P<pre>\A|\n\n)(?P<word>[^\n]+) \((?P<pos>[^()\n]+)\)\n\n").unwrap();
+
+        return re_headword
+            .replace_all(output, |caps: &Captures| {
+                format!(
+                    "{}{} {}\n\n",
+                    &caps["pre"],
+                    (&caps["word"]).bold().cyan(),
+                    format!("({})", &caps["pos"]).italic().yellow()
+                )
+            })
+            .into_owned();
+    }
+
+    // Headword is the first line, or the first line after a separator
+    let re_headword = Regex::new(r"(?P<pre>\A|\n-{3,}\n\n)(?P<word>[^\n]+)").unwrap();
+    let after_1 = re_headword.replace_all(output, |caps: &Captures| {
+        format!("{}{}", &caps["pre"], (&caps["word"]).bold().cyan())
+    });
+
+    // Part-of-speech labels open their own paragraph
+    let re_pos = Regex::new(
+        r"(?m)^(?P<pos>(?:(?:n|v|tr|intr|aux|adj|adv|pron|prep|conj|interj|abbr|pref|suff|pl)\.)+)(?P<post>\s|$)",
+    )
+    .unwrap();
+    let after_2 = re_pos.replace_all(&after_1, |caps: &Captures| {
+        format!("{}{}", (&caps["pos"]).italic().yellow(), &caps["post"])
+    });
+
+    // Numbered and (indented) lettered sense markers
+    let re_senses = Regex::new(r"(?m)^(?P<label>\d+\.| +[a-z]\.)(?P<gap>  )").unwrap();
+    let after_3 = re_senses.replace_all(&after_2, |caps: &Captures| {
+        format!("{}{}", (&caps["label"]).bold().green(), &caps["gap"])
+    });
+
+    after_3.into_owned()
+}
 
 #[must_use]
 // Take list of elements and compile them into a string (as appropriate)
-pub fn compile_results(etym_mode: bool, section_vec: Vec<ElementRef>) -> String {
+// With no elements, that's an empty string; there's nothing to index into
+pub fn compile_results(source: Source, section_vec: Vec<ElementRef>, sections: Sections) -> String {
+    let mut results = String::new();
+
+    match source {
+        Source::Tfd => {
+            // If definition, set up a few more selectors for desired elements
+            let element_selectors = Selector::parse("div.pseg, h2, hr.hmsep").unwrap();
+
+            // Push selected elements from the first section, or from each in turn
+            let count = sections.count(section_vec.len());
+            for (i, section) in section_vec.iter().take(count).enumerate() {
+                if i > 0 {
+                    results.push_str(SECTION_SEPARATOR);
+                }
+
+                for element in section.select(&element_selectors) {
+                    results.push_str(&element.html());
+                }
+            }
+        }
+        Source::Etymonline => {
+            // If etymology, just push everything from any sections
+            for section in section_vec {
+                results.push_str(&section.html());
+            }
+        }
+        Source::Wiktionary => {
+            // Footnote markers would only point to references we don't keep
+            let re_refs =
+                Regex::new(r#"(?s)<sup[^>]*class="[^"]*reference[^"]*"[^>]*>.*?</sup>"#).unwrap();
+
+            for section in section_vec {
+                results.push_str(&re_refs.replace_all(&section.html(), ""));
+            }
+        }
+    }
+
+    results
+}
+
+#[must_use]
+// Gather TFD's phrasal verbs and idioms, each under a heading, to follow the senses
+// compile_results leaves these out; other sources have nothing like them
+// Many entries have neither, in which case this is an empty string
+pub fn compile_idioms(source: Source, section_vec: &[ElementRef], sections: Sections) -> String {
     let mut results = String::new();
 
-    if etym_mode {
-        // If etymology, just push everything from any sections
-        for section in section_vec {
-            results.push_str(&section.html());
-        }
-    } else {
-        // If definition, set up a few more selectors for desired elements
-        let element_selectors = Selector::parse("div.pseg, h2, hr.hmsep").unwrap();
+    if source != Source::Tfd {
+        return results;
+    }
+
+    let count = sections.count(section_vec.len());
+    for (class, heading) in [("pvseg", "Phrasal Verbs"), ("idmseg", "Idioms")] {
+        let block_selector = Selector::parse(&format!("div.{class}")).unwrap();
+
+        let blocks: Vec<String> = section_vec
+            .iter()
+            .take(count)
+            .flat_map(|section| section.select(&block_selector))
+            .map(|block| block.html())
+            .collect();
+
+        if !blocks.is_empty() {
+            results.push_str("<h3>");
+            results.push_str(heading);
+            results.push_str("</h3>");
+            results.push_str(&blocks.concat());
+        }
+    }
+
+    results
+}
+
+// Like compile_results, but keep only the requested part of speech and/or sense
+// TFD groups senses by part of speech, each in a div.pseg labeled with an <i>
+// Etymologies have nothing comparable, so they're left whole
+pub fn filter_senses(
+    source: Source,
+    section_vec: &[ElementRef],
+    sections: Sections,
+    filter: &SenseFilter,
+) -> Result<String, GlossError> {
+    if source.mode() == Mode::Etymology {
+        return Ok(compile_results(source, section_vec.to_vec(), sections));
+    }
+
+    let element_selectors = Selector::parse("div.pseg, h2, hr.hmsep").unwrap();
+
+    let mut results = String::new();
+    let mut available: Vec<String> = Vec::new();
+    let mut matched = false;
+
+    let count = sections.count(section_vec.len());
+    for (i, section) in section_vec.iter().take(count).enumerate() {
+        if i > 0 {
+            results.push_str(SECTION_SEPARATOR);
+        }
+
+        for element in section.select(&element_selectors) {
+            // Headwords and separators are always kept
+            if element.value().name() != "div" {
+                results.push_str(&element.html());
+                continue;
+            }
+
+            let children: Vec<ElementRef> =
+                element.children().filter_map(ElementRef::wrap).collect();
+            let label = children.iter().find(|child| child.value().name() == "i");
+            let label_text = label.map_or_else(String::new, |label| {
+                label.text().collect::<String>().trim().to_owned()
+            });
+
+            // Numbered senses are in div.ds-list; a lone sense is in div.ds-single
+            let senses: Vec<&ElementRef> = children
+                .iter()
+                .filter(|child| {
+                    child
+                        .value()
+                        .classes()
+                        .any(|class| class == "ds-list" || class == "ds-single")
+                })
+                .collect();
+
+            // Note what we've seen, in case nothing matches
+            if !label_text.is_empty() {
+                available.push(match senses.len() {
+                    0 | 1 => label_text.clone(),
+                    n => format!("{label_text} 1–{n}"),
+                });
+            }
+
+            if !filter.matches_pos(&label_text) {
+                continue;
+            }
+
+            match filter.sense {
+                None => results.push_str(&element.html()),
+                Some(n) => {
+                    let Some(sense) = senses.get(n.saturating_sub(1)) else {
+                        continue;
+                    };
+
+                    results.push_str(r#"<div class="pseg">"#);
+                    if let Some(label) = label {
+                        results.push_str(&label.html());
+                    }
+                    results.push_str(&sense.html());
+                    results.push_str("</div>");
+                }
+            }
+
+            matched = true;
+        }
+    }
+
+    if matched {
+        Ok(results)
+    } else {
+        Err(GlossError::NoSuchSense(available.join(", ")))
+    }
+}
+
+// Where a page lists its "did you mean" suggestions
+fn suggestions_selector(source: Source) -> Selector {
+    match source {
+        Source::Tfd => Selector::parse("ul.suggestions li").unwrap(),
+        Source::Etymonline => Selector::parse(r#"a[class^="word__name--"]"#).unwrap(),
+        Source::Wiktionary => Selector::parse(".mw-search-result-heading a").unwrap(),
+    }
+}
+
+#[must_use]
+// Collect "did you mean" suggestions from a page into a string (empty if none)
+pub fn compile_suggestions(source: Source, parsed_chunk: &Html) -> String {
+    let mut results = String::new();
+
+    for element in parsed_chunk.select(&suggestions_selector(source)) {
+        match source {
+            Source::Tfd | Source::Wiktionary => results.push_str(&element.html()),
+            // Search results are bare links, so give each its own paragraph
+            Source::Etymonline => {
+                results.push_str("<p>");
+                results.push_str(&element.html());
+                results.push_str("</p>");
+            }
+        }
+    }
+
+    results
+}
+
+#[must_use]
+// Collect "did you mean" suggestions from a page as plain words (empty if none)
+pub fn get_suggestions(source: Source, parsed_chunk: &Html) -> Vec<String> {
+    let mut suggestions: Vec<String> = Vec::new();
+
+    for element in parsed_chunk.select(&suggestions_selector(source)) {
+        let text = element.text().collect::<String>().trim().to_owned();
+
+        if !text.is_empty() && !suggestions.contains(&text) {
+            suggestions.push(text);
+        }
+    }
+
+    suggestions
+}
+
+// Set up an HTTP client, which can be reused across requests
+// The connect timeout is usually the shorter: an unreachable host should fail fast
+// With a proxy, all requests go through it, except for hosts listed in $NO_PROXY
+pub fn build_client(
+    timeout: Duration,
+    connect_timeout: Duration,
+    user_agent: &str,
+    proxy: Option<&str>,
+) -> Result<Client, GlossError> {
+    // Checked here, since reqwest would only say "builder error"
+    let user_agent = HeaderValue::from_str(user_agent).map_err(|source| GlossError::UserAgent {
+        agent: user_agent.to_owned(),
+        source,
+    })?;
+
+    let mut builder = Client::builder()
+        .user_agent(user_agent)
+        .timeout(timeout)
+        .connect_timeout(connect_timeout);
+
+    if let Some(url) = proxy {
+        let proxy = Proxy::all(url).map_err(|source| GlossError::Proxy {
+            url: url.to_owned(),
+            source,
+        })?;
+        builder = builder.proxy(proxy.no_proxy(NoProxy::from_env()));
+    }
+
+    let client = builder
+        .build()
+        .map_err(|e| GlossError::http(e, "Failed to build HTTP client"))?;
+
+    Ok(client)
+}
+
+// What a site said to identify a version of a page (ETag and/or Last-Modified)
+// Sent back later, it lets the site answer "unchanged" instead of sending the page again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    #[must_use]
+    // Did the site give us anything to go on?
+    pub const fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+// A fetched page, and where it was fetched from in the end
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page {
+    pub text: String,
+    // After any redirects (e.g. to a canonical spelling, or a search page)
+    pub url: Url,
+    pub validators: Validators,
+}
+
+// Make HTTP request and read response body into string, noting the final URL
+pub fn get_response_text(client: &Client, lookup_url: &str) -> Result<Page, GlossError> {
+    get_page(client, lookup_url, &Validators::default())
+}
+
+// Like get_response_text, but ask for the page only if it differs from the version described
+// If it doesn't, that's GlossError::NotModified; with no validators, the request is as usual
+pub fn get_page(
+    client: &Client,
+    lookup_url: &str,
+    validators: &Validators,
+) -> Result<Page, GlossError> {
+    let mut request = client.get(lookup_url);
+
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let response = request
+        .send()
+        .map_err(|e| GlossError::http(e, "Failed to complete HTTP request"))?;
+
+    // A missing page means a missing entry; other failures should be surfaced
+    // (and possibly retried), rather than parsed as if they were entries
+    let status = response.status();
+
+    if status == StatusCode::NOT_MODIFIED && !validators.is_empty() {
+        return Err(GlossError::NotModified);
+    }
+
+    if status == StatusCode::NOT_FOUND {
+        return Err(GlossError::NotFound);
+    }
+
+    // Retry-After can also be an HTTP date, but the sites we know send seconds
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+
+        return Err(GlossError::RateLimited(retry_after));
+    }
+
+    if !status.is_success() {
+        return Err(GlossError::Status(status));
+    }
+
+    let url = response.url().clone();
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned)
+    };
+    let validators = Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    };
+    let text = response
+        .text()
+        .map_err(|e| GlossError::http(e, "Failed to decode HTTP response body"))?;
+
+    Ok(Page {
+        text,
+        url,
+        validators,
+    })
+}
+
+#[must_use]
+// Cull certain elements from the HTML fragment, based on CSS selectors
+pub fn get_section_vec(source: Source, parsed_chunk: &Html) -> Vec<ElementRef<'_>> {
+    // Set up a selector for the relevant section
+    let section_selector = match source {
+        Source::Tfd => return get_dictionary_sections(parsed_chunk, Dictionary::default()),
+        Source::Etymonline => Selector::parse(r#"div[class^="word--"]:not([class*="word_4pc"]) h1, div[class^="word--"]:not([class*="word_4pc"]) p"#).unwrap(),
+        Source::Wiktionary => return wiktionary_etymology(parsed_chunk),
+    };
+
+    // Run the select iterator and collect the result(s) in a vec
+    // For definition lookup, this should yield either one item, or nothing
+    // For etymology lookup, it could yield multiple sections
+    let section_vec: Vec<ElementRef> = parsed_chunk.select(&section_selector).collect();
+
+    section_vec
+}
+
+#[must_use]
+// Select the sections of one of TFD's dictionaries (get_section_vec takes the default's)
+pub fn get_dictionary_sections(parsed_chunk: &Html, dictionary: Dictionary) -> Vec<ElementRef<'_>> {
+    let section_selector = Selector::parse(&format!(
+        r#"div#Definition section[data-src="{}"]"#,
+        dictionary.data_src()
+    ))
+    .unwrap();
+
+    parsed_chunk.select(&section_selector).collect()
+}
+
+// Wiktionary's etymology isn't a container, but a heading followed by paragraphs
+// Take those after the first such heading (English comes first), up to the next heading
+// Newer pages wrap the heading (with its ID) in a div; older ones put the ID on a span inside
+fn wiktionary_etymology(parsed_chunk: &Html) -> Vec<ElementRef<'_>> {
+    let marker_selector = Selector::parse(
+        r#"h3[id^="Etymology"], h4[id^="Etymology"], span.mw-headline[id^="Etymology"]"#,
+    )
+    .unwrap();
+
+    let Some(marker) = parsed_chunk.select(&marker_selector).next() else {
+        return Vec::new();
+    };
+
+    let is_heading = |element: &ElementRef| {
+        matches!(element.value().name(), "h2" | "h3" | "h4" | "h5")
+            || element
+                .value()
+                .classes()
+                .any(|class| class.starts_with("mw-heading"))
+    };
+
+    let heading = marker
+        .parent()
+        .and_then(ElementRef::wrap)
+        .filter(is_heading)
+        .unwrap_or(marker);
+
+    heading
+        .next_siblings()
+        .filter_map(ElementRef::wrap)
+        .take_while(|element| !is_heading(element))
+        .filter(|element| matches!(element.value().name(), "p" | "ul" | "ol" | "dl"))
+        .collect()
+}
+
+#[must_use]
+// Count the senses in compiled (or cached) HTML: each numbered one, and any lone sense
+// Lettered subsenses belong to their numbered sense and aren't counted on their own
+pub fn count_senses(source: Source, parsed: &Html) -> usize {
+    if source.mode() == Mode::Etymology {
+        return 0;
+    }
+
+    let sense_selector = Selector::parse("div.pseg div.ds-list, div.pseg div.ds-single").unwrap();
+    parsed.select(&sense_selector).count()
+}
+
+#[must_use]
+// Collect the usage examples in compiled (or cached) HTML, in order, as text
+// These are what strip_examples removes: TFD's span.illustration; etymologies have none
+pub fn get_examples(source: Source, parsed: &Html) -> Vec<String> {
+    if source.mode() == Mode::Etymology {
+        return Vec::new();
+    }
+
+    let example_selector = Selector::parse("span.illustration").unwrap();
+    parsed
+        .select(&example_selector)
+        .map(|example| {
+            let text = example.text().collect::<String>();
+            text.split_whitespace().collect::<Vec<_>>().join(" ")
+        })
+        .filter(|example| !example.is_empty())
+        .collect()
+}
+
+#[must_use]
+// Pull out the pronunciation respelling, if any, so it can be shown on its own
+// Only definition entries have one; the first match belongs to the headword
+pub fn get_pronunciation(source: Source, section_vec: &[ElementRef]) -> Option<String> {
+    if source.mode() == Mode::Etymology {
+        return None;
+    }
+
+    let pron_selector = Selector::parse("span.pron").unwrap();
+    let pron = section_vec.first()?.select(&pron_selector).next()?;
+
+    let text = pron.text().collect::<String>().trim().to_owned();
+    (!text.is_empty()).then_some(text)
+}
+
+#[must_use]
+// Pull out inflected forms (plurals, comparatives, verb forms) from headword lines
+// Each part of speech gets a line like "n. pl. isth·mus·es or isth·mi (-mī′)"
+pub fn get_inflections(source: Source, section_vec: &[ElementRef]) -> Vec<String> {
+    let Some(section) = section_vec
+        .first()
+        .filter(|_| source.mode() == Mode::Definition)
+    else {
+        return Vec::new();
+    };
+
+    // Whatever follows the part-of-speech abbreviation(s) is the inflection clause
+    let re_clause = Regex::new(
+        r"^\s*(?:(?:tr|intr)\.)?(?:n|v|adj|adv|pron|prep|conj|interj)\.\s+(?P<clause>.+)$",
+    )
+    .unwrap();
+    let re_pron = Regex::new(r"\([^()]*\)").unwrap();
+    let re_label =
+        Regex::new(r"^(?:(?:pl|comp|superl)\.|comparative|superlative|also)\s+").unwrap();
+
+    let pseg_selector = Selector::parse("div.pseg").unwrap();
+    let mut inflections: Vec<String> = Vec::new();
+
+    for pseg in section.select(&pseg_selector) {
+        let line = headword_line(pseg);
+        let Some(caps) = re_clause.captures(line.trim()) else {
+            continue;
+        };
+
+        // Forms are separated by commas or "or"; labels and respellings go
+        let clause = re_pron.replace_all(&caps["clause"], "");
+        for piece in clause.split([',', ';']).flat_map(|p| p.split(" or ")) {
+            let mut form = piece.trim();
+            while let Some(m) = re_label.find(form) {
+                form = &form[m.end()..];
+            }
+
+            let form = form.replace('·', "");
+            let is_word = form
+                .chars()
+                .all(|c| c.is_alphabetic() || matches!(c, '-' | '\'' | '’' | ' '));
+
+            if !form.is_empty() && is_word && !inflections.contains(&form) {
+                inflections.push(form);
+            }
+        }
+    }
+
+    inflections
+}
+
+#[must_use]
+// Pull out other names for the word, given within senses as "Also called throwback."
+// The names are in bold, up to the end of the sentence
+pub fn get_also_called(source: Source, section_vec: &[ElementRef]) -> Vec<String> {
+    let Some(section) = section_vec
+        .first()
+        .filter(|_| source.mode() == Mode::Definition)
+    else {
+        return Vec::new();
+    };
+
+    let sense_selector = Selector::parse("div.ds-list, div.ds-single, div.sds-list").unwrap();
+    let mut names: Vec<String> = Vec::new();
+
+    for sense in section.select(&sense_selector) {
+        let mut in_clause = false;
+
+        for child in sense.children() {
+            match child.value() {
+                Node::Text(text) if in_clause && text.contains('.') => in_clause = false,
+                Node::Text(text) if text.contains("Also called") => in_clause = true,
+                Node::Element(element) if in_clause && element.name() == "b" => {
+                    if let Some(element) = ElementRef::wrap(child) {
+                        let name = element.text().collect::<String>().trim().to_owned();
+                        if !name.is_empty() && !names.contains(&name) {
+                            names.push(name);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    names
+}
+
+#[must_use]
+// Pull out usage notes, which AHD sets apart in blocks beginning "Usage Note:"
+// Only the outermost such block counts, in case it's wrapped in another div
+pub fn get_usage_notes(source: Source, section_vec: &[ElementRef]) -> Vec<String> {
+    let Some(section) = section_vec
+        .first()
+        .filter(|_| source.mode() == Mode::Definition)
+    else {
+        return Vec::new();
+    };
+
+    let div_selector = Selector::parse("div").unwrap();
+    let note_text = |element: ElementRef| {
+        let text = element.text().collect::<String>();
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        text.strip_prefix("Usage Note:")
+            .map(|note| note.trim().to_owned())
+    };
+
+    section
+        .select(&div_selector)
+        .filter(|div| {
+            div.ancestors()
+                .filter_map(ElementRef::wrap)
+                .all(|ancestor| ancestor.value().name() != "div" || note_text(ancestor).is_none())
+        })
+        .filter_map(note_text)
+        .filter(|note| !note.is_empty())
+        .collect()
+}
+
+// Text of a part-of-speech segment, up to where its senses begin
+fn headword_line(pseg: ElementRef) -> String {
+    let mut line = String::new();
 
-        // Push selected elements from first/only section
-        for element in section_vec[0].select(&element_selectors) {
-            results.push_str(&element.html());
+    for child in pseg.children() {
+        match child.value() {
+            Node::Text(text) => line.push_str(text),
+            Node::Element(element) if element.name() == "div" => break,
+            Node::Element(_) => {
+                if let Some(element) = ElementRef::wrap(child) {
+                    line.extend(element.text());
+                }
+            }
+            _ => {}
         }
     }
 
-    results
+    line
 }
 
-// Make HTTP request and read response body into string
-pub fn get_response_text(lookup_url: &str) -> Result<String, anyhow::Error> {
-    let response_text = reqwest::blocking::get(lookup_url)
-        .context("Failed to complete HTTP request")?
-        .text()
-        .context("Failed to decode HTTP response body")?;
+#[must_use]
+// Keep only the first few paragraphs of final output, marking any cut
+pub fn limit_paragraphs(output: &str, limit: usize) -> String {
+    let paragraphs: Vec<&str> = output.trim_end().split("\n\n").collect();
+
+    if paragraphs.len() <= limit {
+        return output.to_owned();
+    }
 
-    Ok(response_text)
+    paragraphs[..limit].join("\n\n") + "\n\n[…]\n"
 }
 
 #[must_use]
-// Cull certain elements from the HTML fragment, based on CSS selectors
-pub fn get_section_vec(etym_mode: bool, parsed_chunk: &Html) -> Vec<ElementRef> {
-    // Set up a selector for the relevant section
-    let section_selector = if etym_mode {
-        Selector::parse(r#"div[class^="word--"]:not([class*="word_4pc"]) h1, div[class^="word--"]:not([class*="word_4pc"]) p"#).unwrap()
+// Re-wrap each paragraph of final output to the given width (in terminal columns)
+// List items keep their hanging indent; non-breaking spaces still don't break
+pub fn rewrap(output: &str, width: usize) -> String {
+    let re_prefix =
+        Regex::new(r"^(?P<indent> *)(?P<marker>(?:\d+\.|[-*+]) +|[a-z]\. {2,})?").unwrap();
+
+    let paragraphs: Vec<String> = output
+        .trim_end_matches('\n')
+        .split("\n\n")
+        .map(|paragraph| {
+            let prefix = re_prefix.captures(paragraph).unwrap(); // Can match nothing
+            let indent = &prefix["indent"];
+            let marker = prefix.name("marker").map_or("", |marker| marker.as_str());
+
+            let words: Vec<&str> = paragraph[prefix[0].len()..]
+                .split([' ', '\n'])
+                .filter(|word| !word.is_empty())
+                .collect();
+
+            let hanging = indent.to_owned() + &" ".repeat(marker.len());
+            let options = textwrap::Options::new(width)
+                .initial_indent(&prefix[0])
+                .subsequent_indent(&hanging);
+
+            textwrap::fill(&words.join(" "), options)
+        })
+        .collect();
+
+    let rewrapped = paragraphs.join("\n\n");
+
+    if output.ends_with('\n') {
+        rewrapped + "\n"
     } else {
-        Selector::parse(r#"div#Definition section[data-src="hm"]"#).unwrap()
+        rewrapped
+    }
+}
+
+// What a lookup found, and where, so that it can be attributed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupResult {
+    // The entry, as plain text (or, from lookup_html, as HTML)
+    pub text: String,
+    // The page it came from (after any redirects)
+    pub source_url: String,
+    pub mode: Mode,
+}
+
+// Run the scraping half of the pipeline for a word: fetch, chunk, select, compile
+// The HTML fragment is left for the caller to render, with pandoc_primary or otherwise
+pub fn lookup_html(word: &str, mode: Mode, client: &Client) -> Result<LookupResult, GlossError> {
+    let source = Source::default_for(mode);
+    let page = get_response_text(client, &build_url(source, &normalize_word(word)))?;
+    let parsed_chunk = take_chunk(source, &page.text);
+    let section_vec = get_section_vec(source, &parsed_chunk);
+
+    if section_vec.is_empty() {
+        return Err(GlossError::NotFound);
+    }
+
+    Ok(LookupResult {
+        text: compile_results(source, section_vec, Sections::First),
+        source_url: page.url.to_string(),
+        mode,
+    })
+}
+
+// Run the whole pipeline for a word: fetch, chunk, select, compile, convert
+// This always goes to the network; caching is left to the caller
+pub fn lookup(word: &str, mode: Mode, client: &Client) -> Result<LookupResult, GlossError> {
+    let result = lookup_html(word, mode, client)?;
+
+    Ok(LookupResult {
+        text: render_results(
+            Source::default_for(mode),
+            Format::Plain,
+            Renderer::default(),
+            &result.text,
+        )?,
+        ..result
+    })
+}
+
+// Render compiled results in the given format: HTML as is, or text by the given means
+// Without the "pandoc" feature, only our own plain text is to be had (and vice versa)
+#[cfg_attr(not(feature = "pandoc"), allow(unused_variables))]
+pub fn render_results(
+    source: Source,
+    format: Format,
+    renderer: Renderer,
+    results: &str,
+) -> Result<String, GlossError> {
+    match (renderer, format) {
+        (_, Format::Html) => Ok(results.to_owned() + "\n"),
+        #[cfg(feature = "render")]
+        (Renderer::Builtin, Format::Plain) => Ok(render_plain(results)),
+        #[cfg(feature = "pandoc")]
+        _ => pandoc_primary(source, format, results),
+        #[cfg(not(feature = "pandoc"))]
+        _ => Err(GlossError::PandocMissing),
+    }
+}
+
+#[must_use]
+// Decode any HTML entities left in text, as when the scraped markup escaped them twice
+// Each pass undoes one level of escaping ("&amp;#160;" takes two); unknown names stay
+pub fn decode_entities(text: &str) -> String {
+    let re_entity = Regex::new(
+        r"&(?:#(?P<dec>\d{1,7})|#[xX](?P<hex>[0-9a-fA-F]{1,6})|(?P<name>amp|lt|gt|quot|apos|nbsp));",
+    )
+    .unwrap();
+
+    let mut decoded = text.to_owned();
+
+    for _ in 0..3 {
+        let next = re_entity.replace_all(&decoded, |caps: &Captures| {
+            let character = match (caps.name("dec"), caps.name("hex"), caps.name("name")) {
+                (Some(dec), _, _) => dec.as_str().parse().ok().and_then(char::from_u32),
+                (_, Some(hex), _) => u32::from_str_radix(hex.as_str(), 16)
+                    .ok()
+                    .and_then(char::from_u32),
+                (_, _, Some(name)) => match name.as_str() {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    _ => Some('\u{a0}'),
+                },
+                _ => None,
+            };
+
+            character.map_or_else(|| caps[0].to_owned(), String::from)
+        });
+
+        if next == decoded {
+            break;
+        }
+        decoded = next.into_owned();
+    }
+
+    decoded
+}
+
+#[must_use]
+// Replace non-breaking spaces (of any width) with ordinary ones
+pub fn ascii_spaces(text: &str) -> String {
+    text.replace(['\u{a0}', '\u{2007}', '\u{202f}'], " ")
+}
+
+#[must_use]
+// Pick the featured word out of a site's word-of-the-day page
+pub fn get_word_of_the_day(source: Source, page: &Html) -> Option<String> {
+    let wotd_selector = match source {
+        Source::Tfd => Selector::parse("#Content_CA_WOD_0_DataZone h3 a").unwrap(),
+        Source::Etymonline => {
+            Selector::parse(r#"[class*="word-of-the-day"] a[href^="/word/"]"#).unwrap()
+        }
+        Source::Wiktionary => Selector::parse("#WOTD-rss-title").unwrap(),
     };
 
-    // Run the select iterator and collect the result(s) in a vec
-    // For definition lookup, this should yield either one item, or nothing
-    // For etymology lookup, it could yield multiple sections
-    let section_vec: Vec<ElementRef> = parsed_chunk.select(&section_selector).collect();
+    // Etymonline links may carry a part of speech, e.g. "forest (n.)"
+    let word = page
+        .select(&wotd_selector)
+        .next()?
+        .text()
+        .collect::<String>()
+        .split(" (")
+        .next()?
+        .trim()
+        .to_lowercase();
 
-    section_vec
+    (!word.is_empty()).then_some(word)
 }
 
-// Function to convert to plain text with Pandoc, as a final step
-// This used to be duplicated in pandoc_primary, but jscpd was complaining
-pub fn pandoc_plain(input: &str) -> Result<String, anyhow::Error> {
-    // String is again written to a tempfile for Pandoc
-    let mut input_file = NamedTempFile::new().context("Failed to create tempfile")?;
-    write!(input_file, "{input}").context("Failed to write to tempfile")?;
-
-    let pandoc = Command::new("pandoc")
-        .arg(input_file.path())
-        .arg("-t")
-        .arg("plain")
-        .output()
-        .context("Failed to execute Pandoc")?;
-
-    let output = str::from_utf8(&pandoc.stdout)
-        .context("Failed to convert Pandoc output to string")?
-        .to_owned();
-
-    Ok(output)
-}
-
-// Main Pandoc function
-pub fn pandoc_primary(etym_mode: bool, results: &str) -> Result<String, anyhow::Error> {
-    // Write results string into a tempfile to pass to Pandoc
-    let mut input_file_1 = NamedTempFile::new().context("Failed to create tempfile")?;
-    write!(input_file_1, "{results}").context("Failed to write to tempfile")?;
-
-    let pandoc_1 = Command::new("pandoc")
-        .arg(input_file_1.path())
-        .arg("-f")
-        .arg("html+smart-native_divs")
-        .arg("-t")
-        .arg("markdown")
-        .arg("--wrap=none")
-        .output()
-        .context("Failed to execute Pandoc")?;
-
-    // Take first Pandoc output as a string
-    let output_1 =
-        str::from_utf8(&pandoc_1.stdout).context("Failed to convert Pandoc output to string")?;
-
-    // Make regex (and simple text) replacements, depending on search mode
-    if etym_mode {
-        // Remove any figures
-        let re_figures = Regex::new(r"(?m)\n\n!\[.+$").unwrap();
-        let after_1 = re_figures.replace_all(output_1, "");
-
-        // Un-escape double quotes
-        // I don't know why Pandoc is outputting these to begin with
-        let after_2 = after_1.replace(r#"\\""#, r#"""#);
-
-        let final_output = pandoc_plain(&after_2)?;
-        Ok(final_output)
-    } else {
-        // Un-bold numbered list labels
-        let re_list_1 = Regex::new(r"\n\*\*(?P<a>\d+\.)\*\*").unwrap();
-        let after_1 = re_list_1.replace_all(output_1, "\n$a");
+#[must_use]
+// Find the URLs of any audio pronunciations, in order and without repeats
+// TFD marks them as span.snd (with a file ID) or, on older pages, a.snd (with a link)
+pub fn get_audio_urls(source: Source, section_vec: &[ElementRef]) -> Vec<String> {
+    if source != Source::Tfd {
+        return Vec::new();
+    }
 
-        // Un-bold and indent lettered list labels
-        let re_list_2 = Regex::new(r"\n\*\*(?P<b>[a-z]\.)\*\*").unwrap();
-        let after_2 = re_list_2.replace_all(&after_1, "\n    $b");
+    let snd_selector = Selector::parse(".snd[data-snd], a.snd[href]").unwrap();
+    let mut urls: Vec<String> = Vec::new();
 
-        // Un-escape double quotes
-        let after_3 = after_2.replace(r#"\\""#, r#"""#);
+    for element in section_vec
+        .iter()
+        .flat_map(|section| section.select(&snd_selector))
+    {
+        let url = match (
+            element.value().attr("data-snd"),
+            element.value().attr("href"),
+        ) {
+            (Some(id), _) => format!("https://img.tfd.com/hm/mp3/{id}.mp3"),
+            (None, Some(href)) if href.starts_with("https:") || href.starts_with("http:") => {
+                href.to_owned()
+            }
+            (None, Some(href)) if href.starts_with("//") => "https:".to_owned() + href,
+            (None, Some(href)) => {
+                "https://www.thefreedictionary.com/".to_owned() + href.trim_start_matches('/')
+            }
+            (None, None) => continue,
+        };
 
-        let final_output = pandoc_plain(&after_3)?;
-        Ok(final_output)
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
     }
+
+    urls
 }
 
 #[must_use]
-// Take only part of the response text, for faster parsing
-pub fn take_chunk(response_text: &str) -> Html {
-    // In definition mode, we split the document
-    // Otherwise we could blow a bunch of time parsing the whole thing
-    // In etymology mode, this shouldn't do anything
-    let chunks: Vec<&str> = response_text.split(r#"<div id="Thesaurus">"#).collect();
+// The part of the response text worth parsing, if we can tell where it ends
+// For TFD, that's before the thesaurus; the marker may vary in spacing, quoting, or case
+pub fn find_chunk(source: Source, response_text: &str) -> Option<&str> {
+    match source {
+        Source::Tfd => {
+            let re_marker =
+                Regex::new(r#"(?i)<div\s[^>]*\bid\s*=\s*["']?Thesaurus\b["']?[^>]*>"#).unwrap();
+            re_marker
+                .find(response_text)
+                .map(|marker| &response_text[..marker.start()])
+        }
+        Source::Etymonline | Source::Wiktionary => Some(response_text),
+    }
+}
 
-    // Parse the first chunk, which is the one we want
-    // For an etymology entry, the "first chunk" is the whole document
-    Html::parse_fragment(chunks[0])
+#[must_use]
+// Take only part of the response text, for faster parsing
+// Without the marker we have no choice but to parse the whole thing
+pub fn take_chunk(source: Source, response_text: &str) -> Html {
+    Html::parse_fragment(find_chunk(source, response_text).unwrap_or(response_text))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_server::serve;
+
+    // A client like the one we'd build by default
+    fn test_client() -> Client {
+        build_client(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            USER_AGENT,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn full_sequence(mode: Mode, word: &str) -> String {
+        let client = build_client(
+            Duration::from_secs(10),
+            Duration::from_secs(5),
+            USER_AGENT,
+            None,
+        )
+        .unwrap();
+        let result = lookup(word, mode, &client).unwrap();
+        assert_eq!(
+            result.source_url,
+            build_url(Source::default_for(mode), word)
+        );
+        assert_eq!(result.mode, mode);
+        result.text
+    }
+
+    #[test]
+    fn def_offline_pipeline() {
+        let source = Source::Tfd;
+        let response_text = r#"<html><body><div id="Definition"><section data-src="hm"><h2>at·a·vism</h2><div class="pseg"><i>n.</i><div class="ds-list"><b>1. </b>The reappearance of a characteristic.</div></div><div class="etyseg">Unwanted</div></section></div><div id="Thesaurus"><section data-src="hm"><h2>Ignored</h2></section></div></body></html>"#;
+
+        let parsed_chunk = take_chunk(source, response_text);
+        let section_vec = get_section_vec(source, &parsed_chunk);
+        assert_eq!(section_vec.len(), 1);
+        assert_eq!(get_pronunciation(source, &section_vec), None);
+
+        let results = compile_results(source, section_vec, Sections::First);
+        assert!(results.starts_with("<h2>at·a·vism</h2><div class=\"pseg\">"));
+        assert!(!results.contains("Unwanted"));
+        assert!(!results.contains("Ignored"));
+    }
+
+    #[test]
+    fn sense_filter() {
+        let source = Source::Tfd;
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>run</h2><div class="pseg"><i>intr.v.</i><div class="ds-list"><b>1. </b>To move swiftly on foot.</div><div class="ds-list"><b>2. </b>To retreat rapidly.</div></div><div class="pseg"><i>n.</i><div class="ds-single">A pace faster than a walk.</div></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(source, response_text);
+        let section_vec = get_section_vec(source, &parsed_chunk);
+
+        let verb = SenseFilter {
+            pos: Some("verb".to_owned()),
+            sense: None,
+        };
+        let results = filter_senses(source, &section_vec, Sections::First, &verb).unwrap();
+        assert!(results.starts_with("<h2>run</h2>"));
+        assert!(results.contains("To retreat rapidly."));
+        assert!(!results.contains("A pace"));
+
+        let second = SenseFilter {
+            pos: None,
+            sense: Some(2),
+        };
+        let results = filter_senses(source, &section_vec, Sections::First, &second).unwrap();
+        assert!(results.contains("<i>intr.v.</i><div class=\"ds-list\"><b>2. </b>"));
+        assert!(!results.contains("To move swiftly"));
+        assert!(!results.contains("<i>n.</i>"));
+
+        let missing = SenseFilter {
+            pos: Some("adj.".to_owned()),
+            sense: None,
+        };
+        assert_eq!(
+            filter_senses(source, &section_vec, Sections::First, &missing)
+                .unwrap_err()
+                .to_string(),
+            "No matching sense; available: intr.v. 1–2, n."
+        );
+    }
+
+    #[test]
+    fn word_normalization() {
+        assert_eq!(normalize_word(" ice  cream "), "ice cream");
+        assert_eq!(normalize_word("Ice\tCream\n"), "ice cream");
+        assert_eq!(normalize_word("   "), "");
+
+        assert_eq!(
+            build_url(Source::Tfd, &normalize_word(" ice  cream ")),
+            build_url(Source::Tfd, "ice cream")
+        );
+        assert_eq!(
+            build_url(Source::Etymonline, &normalize_word("ice   cream")),
+            "https://www.etymonline.com/word/ice%20cream"
+        );
+    }
+
+    #[test]
+    fn punctuated_words() {
+        assert_eq!(normalize_word("O\u{2019}Clock"), "o'clock");
+        assert_eq!(
+            normalize_word("mother\u{2011}in\u{2010}law"),
+            "mother-in-law"
+        );
+        assert_eq!(
+            normalize_word("x\u{2012}ray \u{2212} x\u{fe63}ray \u{ff0d} x\u{2013}ray"),
+            "x-ray - x-ray - x\u{2013}ray"
+        );
+        assert_eq!(
+            cache_key("Jack-o\u{2019}-Lantern"),
+            cache_key("jack-o'-lantern")
+        );
+
+        // Hyphens are left alone in URLs; apostrophes are escaped, whichever was typed
+        assert_eq!(
+            build_url(Source::Tfd, &normalize_word("Mother-in-Law")),
+            "https://www.thefreedictionary.com/mother-in-law"
+        );
+        assert_eq!(
+            build_url(
+                Source::Etymonline,
+                &normalize_word("jack-o\u{2019}-lantern")
+            ),
+            "https://www.etymonline.com/word/jack-o%27-lantern"
+        );
+    }
+
+    #[test]
+    fn cache_keys() {
+        let composed = "caf\u{e9}";
+        let decomposed = "cafe\u{301}";
+        assert_ne!(composed, decomposed);
+
+        assert_eq!(cache_key(decomposed), cache_key(composed));
+        assert_eq!(cache_key(" Caf\u{e9} "), composed);
+        assert_ne!(cache_key("cafe"), cache_key(composed));
+
+        assert_eq!(loose_key(composed), "cafe");
+        assert_eq!(loose_key(decomposed), loose_key(" CAFE "));
+        assert_eq!(loose_key("R\u{e9}sum\u{e9}"), "resume");
+    }
+
+    #[test]
+    fn chunk_marker() {
+        let page = r#"<div id="Definition">atavism</div><div id="Thesaurus">throwback</div>"#;
+        assert_eq!(
+            find_chunk(Source::Tfd, page),
+            Some(r#"<div id="Definition">atavism</div>"#)
+        );
+
+        // Attributes reordered, quoted differently, spaced out, or shouted
+        for marker in [
+            r#"<div class="section" id='Thesaurus' data-src="hc_thes">"#,
+            "<DIV\n  ID = Thesaurus>",
+        ] {
+            let page = format!(r#"<div id="Definition">atavism</div>{marker}throwback</div>"#);
+            assert_eq!(
+                find_chunk(Source::Tfd, &page),
+                Some(r#"<div id="Definition">atavism</div>"#)
+            );
+        }
+
+        // A lookalike isn't the marker, and no marker means the whole page
+        let page = r#"<div id="Definition">atavism</div><div id="ThesaurusAd">ad</div>"#;
+        assert_eq!(find_chunk(Source::Tfd, page), None);
+        assert_eq!(find_chunk(Source::Etymonline, page), Some(page));
+
+        let parsed_chunk = take_chunk(Source::Tfd, page);
+        assert!(parsed_chunk.html().contains("ThesaurusAd"));
+    }
+
+    #[test]
+    fn empty_sections() {
+        for source in [Source::Tfd, Source::Etymonline] {
+            for sections in [Sections::First, Sections::All] {
+                assert_eq!(compile_results(source, Vec::new(), sections), "");
+            }
+        }
+
+        assert!(filter_senses(
+            Source::Tfd,
+            &[],
+            Sections::First,
+            &SenseFilter {
+                pos: Some("n.".to_owned()),
+                sense: None
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn def_full_sections() {
+        let source = Source::Tfd;
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>lead¹</h2><div class="pseg"><i>v.</i></div></section><section data-src="hm"><h2>lead²</h2><div class="pseg"><i>n.</i></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(source, response_text);
+        let section_vec = get_section_vec(source, &parsed_chunk);
+        assert_eq!(section_vec.len(), 2);
+
+        let first = compile_results(source, section_vec.clone(), Sections::First);
+        assert!(first.contains("lead¹"));
+        assert!(!first.contains("lead²"));
+
+        let full = compile_results(source, section_vec, Sections::All);
+        assert_eq!(full.matches(SECTION_SEPARATOR).count(), 1);
+        assert!(
+            full.ends_with(r#"<hr class="hmsep"><h2>lead²</h2><div class="pseg"><i>n.</i></div>"#)
+        );
+    }
+
+    #[test]
+    fn def_audio() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>at·a·vism</h2> <span class="snd" data-snd="A0497800"></span> <span class="pron">(ăt′ə-vĭz′əm)</span><div class="pseg"><i>n.</i><a class="snd" href="//img.tfd.com/hm/mp3/A0497900.mp3"></a><span class="snd" data-snd="A0497800"></span></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+
+        assert_eq!(
+            get_audio_urls(Source::Tfd, &section_vec),
+            [
+                "https://img.tfd.com/hm/mp3/A0497800.mp3",
+                "https://img.tfd.com/hm/mp3/A0497900.mp3"
+            ]
+        );
+        assert!(get_audio_urls(Source::Etymonline, &section_vec).is_empty());
+    }
+
+    #[test]
+    fn url_encoding() {
+        assert_eq!(
+            build_url(Source::Etymonline, "coup d'état"),
+            "https://www.etymonline.com/word/coup%20d%27%C3%A9tat"
+        );
+        assert_eq!(
+            build_url(Source::Tfd, "coup d'état"),
+            "https://www.thefreedictionary.com/coup+d%27%C3%A9tat"
+        );
+        assert_eq!(
+            build_url(Source::Etymonline, "AT&T"),
+            "https://www.etymonline.com/word/AT%26T"
+        );
+        assert_eq!(
+            build_url(Source::Tfd, "and/or"),
+            "https://www.thefreedictionary.com/and%2For"
+        );
+        assert_eq!(
+            build_url(Source::Tfd, "c++"),
+            "https://www.thefreedictionary.com/c%2B%2B"
+        );
+    }
+
+    #[test]
+    fn etym_suggestions() {
+        let source = Source::Etymonline;
+        let response_text = r#"<html><body><div class="word--C9UPa"><a class="word__name--TTbAA" href="/word/forest">forest (n.)</a><p>late 13c.</p></div><div class="word--C9UPa"><a class="word__name--TTbAA" href="/word/forester">forester (n.)</a></div></body></html>"#;
+
+        assert_eq!(
+            build_search_url(source, "forrest").as_deref(),
+            Some("https://www.etymonline.com/search?q=forrest")
+        );
+        assert_eq!(build_search_url(Source::Tfd, "forrest"), None);
+
+        let parsed_chunk = take_chunk(source, response_text);
+        let suggestions = compile_suggestions(source, &parsed_chunk);
+        assert_eq!(suggestions.matches("<p><a ").count(), 2);
+        assert!(suggestions.contains("forester (n.)</a></p>"));
+        assert!(!suggestions.contains("late 13c."));
+
+        assert_eq!(
+            get_suggestions(source, &parsed_chunk),
+            ["forest (n.)", "forester (n.)"]
+        );
+    }
+
+    #[test]
+    fn def_suggestions() {
+        let source = Source::Tfd;
+        let response_text = r#"<html><body><div id="Definition"><p>Word not found in the Dictionary and Encyclopedia.</p><p>Did you mean:</p><ul class="suggestions"><li><a href="/atavism">atavism</a></li><li><a href="/atavist">atavist</a></li><li><a href="/atavism">atavism</a></li></ul></div><div id="Thesaurus"><ul class="suggestions"><li><a href="/avatar">avatar</a></li></ul></div></body></html>"#;
+
+        let parsed_chunk = take_chunk(source, response_text);
+        assert!(get_section_vec(source, &parsed_chunk).is_empty());
+        assert_eq!(
+            get_suggestions(source, &parsed_chunk),
+            ["atavism", "atavist"]
+        );
 
-    fn full_sequence(etym_mode: bool, lookup_url: &str) -> String {
-        let response_text = get_response_text(lookup_url).unwrap();
-        let parsed_chunk = take_chunk(&response_text);
-        let section_vec = get_section_vec(etym_mode, &parsed_chunk);
-        let results = compile_results(etym_mode, section_vec);
+        let found = take_chunk(
+            source,
+            r#"<div id="Definition"><section data-src="hm"><h2>at·a·vism</h2></section></div>"#,
+        );
+        assert!(get_suggestions(source, &found).is_empty());
+    }
+
+    #[test]
+    fn etym_links() {
+        let results = r#"<p>from Latin <a href="/word/foris" class="crossreference">foris</a> "outside" (see <a href="/word/foreign">foreign</a>; and <a href="https://example.com/x">elsewhere</a>, <a href="//cdn.example.com/y">there</a>)</p>"#;
+
+        let linked = absolutize_links(Source::Etymonline, results);
+        assert!(linked.contains(
+            r#"<a href="https://www.etymonline.com/word/foris" class="crossreference">"#
+        ));
+        assert!(linked.contains(r#"<a href="https://www.etymonline.com/word/foreign">"#));
+        assert!(linked.contains(r#"<a href="https://example.com/x">"#));
+        assert!(linked.contains(r#"<a href="//cdn.example.com/y">"#));
+    }
+
+    #[test]
+    fn etym_wiktionary() {
+        let source = Source::Wiktionary;
+
+        // Newer markup: the ID is on the heading, which sits in a div
+        let newer = r##"<div class="mw-parser-output"><div class="mw-heading mw-heading2"><h2 id="English">English</h2></div><div class="mw-heading mw-heading3"><h3 id="Etymology">Etymology</h3><span class="mw-editsection">[edit]</span></div><p>From Middle English <i>forest</i>.<sup id="cite_ref-1" class="reference"><a href="#cite_note-1">[1]</a></sup></p><ul><li>Doublet of <i>foris</i></li></ul><div class="mw-heading mw-heading3"><h3 id="Pronunciation">Pronunciation</h3></div><p>/ˈfɒɹɪst/</p><div class="mw-heading mw-heading3"><h3 id="Etymology_2">Etymology 2</h3></div><p>Unrelated.</p></div>"##;
+
+        // Older markup: the ID is on a span inside the heading
+        let older = r#"<h2><span class="mw-headline" id="English">English</span></h2><h3><span class="mw-headline" id="Etymology">Etymology</span><span class="mw-editsection">[edit]</span></h3><p>From Middle English <i>forest</i>.</p><h3><span class="mw-headline" id="Noun">Noun</span></h3><p>A dense collection of trees.</p>"#;
+
+        for (page, count) in [(newer, 2), (older, 1)] {
+            let parsed_chunk = take_chunk(source, page);
+            let section_vec = get_section_vec(source, &parsed_chunk);
+            assert_eq!(section_vec.len(), count);
+
+            let results = compile_results(source, section_vec, Sections::First);
+            assert!(results.starts_with("<p>From Middle English <i>forest</i>.</p>"));
+            assert!(!results.contains("[1]"));
+            assert!(!results.contains("[edit]"));
+            assert!(!results.contains("Unrelated") && !results.contains("/ˈfɒɹɪst/"));
+        }
+
+        let missing = take_chunk(source, "<p>Wiktionary does not yet have an entry</p>");
+        assert!(get_section_vec(source, &missing).is_empty());
+
+        assert_eq!(
+            build_url(source, "ice cream"),
+            "https://en.wiktionary.org/wiki/ice_cream"
+        );
+    }
+
+    #[test]
+    fn word_of_the_day() {
+        let tfd_page = r#"<html><body><div id="Content_CA_WOD_0_DataZone"><h3><a href="/Atavism">Atavism</a></h3><p>The reappearance of a characteristic.</p></div></body></html>"#;
+        let etym_page = r#"<html><body><section class="word-of-the-day--x1Y2z"><a class="word__name--TTbAA" href="/word/forest">forest (n.)</a></section></body></html>"#;
+
+        assert_eq!(
+            get_word_of_the_day(Source::Tfd, &Html::parse_document(tfd_page)).as_deref(),
+            Some("atavism")
+        );
+        assert_eq!(
+            get_word_of_the_day(Source::Etymonline, &Html::parse_document(etym_page)).as_deref(),
+            Some("forest")
+        );
+        assert_eq!(
+            get_word_of_the_day(Source::Tfd, &Html::parse_document(etym_page)),
+            None
+        );
+    }
+
+    #[test]
+    fn def_pronunciation() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>at·a·vism</h2> <span class="pron">(ăt′ə-vĭz′əm)</span><div class="pseg"><i>n.</i></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+
+        assert_eq!(
+            get_pronunciation(Source::Tfd, &section_vec).as_deref(),
+            Some("(ăt′ə-vĭz′əm)")
+        );
+        assert_eq!(get_pronunciation(Source::Etymonline, &section_vec), None);
+        assert_eq!(get_pronunciation(Source::Tfd, &[]), None);
+    }
+
+    #[test]
+    fn def_inflections() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>isth·mus</h2> <span class="pron">(ĭs′məs)</span><div class="pseg"><i>n.</i> <i>pl.</i> <b>isth·mus·es</b> <i>or</i> <b>isth·mi</b> <span class="pron">(-mī′)</span><div class="ds-list"><b>1. </b>A narrow strip of land.</div></div><div class="pseg"><i>v.</i> <b>ran</b> <span class="pron">(răn)</span>, <b>run</b>, <b>run·ning</b>, <b>runs</b><div class="ds-list"><b>1. </b>To move swiftly.</div></div><div class="pseg"><i>adj.</i> <b>hap·pi·er</b>, <b>hap·pi·est</b></div><div class="pseg"><i>n.</i><div class="ds-list"><b>1. </b>No inflections, pl. here.</div></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+
+        assert_eq!(
+            get_inflections(Source::Tfd, &section_vec),
+            [
+                "isthmuses",
+                "isthmi",
+                "ran",
+                "run",
+                "running",
+                "runs",
+                "happier",
+                "happiest"
+            ]
+        );
+        assert!(get_inflections(Source::Etymonline, &section_vec).is_empty());
+        assert!(get_inflections(Source::Tfd, &[]).is_empty());
+    }
+
+    #[test]
+    fn regional_spelling() {
+        assert_eq!(Region::Uk.respell("color").as_deref(), Some("colour"));
+        assert_eq!(Region::Us.respell("colour").as_deref(), Some("color"));
+        assert_eq!(Region::Uk.respell("realize").as_deref(), Some("realise"));
+        assert_eq!(Region::Us.respell("analysed").as_deref(), Some("analyzed"));
+        assert_eq!(
+            Region::Uk.respell("gray matter").as_deref(),
+            Some("grey matter")
+        );
+
+        // Nothing to change, or nothing safe to change
+        assert_eq!(Region::Uk.respell("colour"), None);
+        assert_eq!(Region::Us.respell("color"), None);
+        assert_eq!(Region::Us.respell("size"), None);
+        assert_eq!(Region::Uk.respell("atavism"), None);
+    }
+
+    #[test]
+    fn sense_count() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>run</h2><div class="pseg"><i>v.</i><div class="ds-list"><b>1. </b>To move swiftly.<div class="sds-list"><b>a. </b>On foot.</div><div class="sds-list"><b>b. </b>By other means.</div></div><div class="ds-list"><b>2. </b>To flee.</div><div class="ds-list"><b>3. </b>To go quickly.</div></div><div class="pseg"><i>adj.</i><div class="ds-single">Melted or liquefied.</div></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+        let results = compile_results(Source::Tfd, section_vec, Sections::First);
+
+        // Counted the same whether just compiled, or read back from the cache
+        let cached =
+            render_results(Source::Tfd, Format::Html, Renderer::default(), &results).unwrap();
+        assert_eq!(
+            count_senses(Source::Tfd, &Html::parse_fragment(&results)),
+            4
+        );
+        assert_eq!(count_senses(Source::Tfd, &Html::parse_fragment(&cached)), 4);
+        assert_eq!(count_senses(Source::Etymonline, &parsed_chunk), 0);
+    }
+
+    #[test]
+    fn def_examples() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>run</h2><div class="pseg"><i>v.</i><div class="ds-list"><b>1. </b>To move swiftly on foot: <span class="illustration">ran to catch the bus</span>; <span class="illustration">ran <i>down</i> the hill</span>.</div><div class="ds-list"><b>2. </b>To flee.</div></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+        let results = compile_results(Source::Tfd, section_vec, Sections::First);
+
+        // Examples stay unless asked otherwise
+        assert_eq!(results.matches(r#"<span class="illustration">"#).count(), 2);
+
+        let terse = strip_examples(Source::Tfd, &results);
+        assert!(terse.contains("<b>1. </b>To move swiftly on foot.</div>"));
+        assert!(terse.contains("<b>2. </b>To flee.</div>"));
+        assert!(!terse.contains("illustration"));
+        assert_eq!(strip_examples(Source::Etymonline, &results), results);
+
+        // Or they can be had on their own
+        let parsed = Html::parse_fragment(&results);
+        assert_eq!(
+            get_examples(Source::Tfd, &parsed),
+            ["ran to catch the bus", "ran down the hill"]
+        );
+        assert!(get_examples(Source::Tfd, &Html::parse_fragment(&terse)).is_empty());
+        assert!(get_examples(Source::Etymonline, &parsed).is_empty());
+    }
+
+    #[test]
+    fn paragraph_limit() {
+        let output = "forest (n.)\n\nlate 13c.,\nfrom Old French.\n\nforest (v.)\n\n1818.\n";
+
+        assert_eq!(
+            limit_paragraphs(output, 2),
+            "forest (n.)\n\nlate 13c.,\nfrom Old French.\n\n[…]\n"
+        );
+        assert_eq!(limit_paragraphs(output, 4), output);
+        assert_eq!(limit_paragraphs(output, 10), output);
+    }
+
+    #[test]
+    fn colorize_def() {
+        let plain = "isth·mus\n\nn. pl. isth·mus·es\n\n1.  A narrow strip of land.\n\n2.  Anatomy\n\n    a.  A narrow strip of tissue.\n";
+        let colored = colorize(Source::Tfd, plain);
+
+        assert!(colored.starts_with(&format!("{}\n\n", "isth·mus".bold().cyan())));
+        assert!(colored.contains(&format!("{} pl.", "n.".italic().yellow())));
+        assert!(colored.contains(&format!("{}  A narrow", "1.".bold().green())));
+        assert!(colored.contains(&format!("{}  A narrow", "    a.".bold().green())));
+        assert!(colored.contains(&format!("{}  Anatomy\n\n", "2.".bold().green())));
+    }
+
+    #[test]
+    fn colorize_etym() {
+        let plain = "forest (n.)\n\nlate 13c., from Old French.\n\nforest (v.)\n\n“cover with trees,” 1818.\n";
+        let colored = colorize(Source::Etymonline, plain);
+
+        let headword = format!("{}", "forest".bold().cyan());
+        assert_eq!(colored.matches(&headword).count(), 2);
+        assert!(colored.contains(&format!("{}\n\n“cover", "(v.)".italic().yellow())));
+        assert!(colored.contains("\n\nlate 13c., from Old French.\n\n"));
+    }
+
+    #[test]
+    fn transient_errors() {
+        // Nothing should be listening on port 1
+        let refused = get_response_text(&test_client(), "http://127.0.0.1:1/").unwrap_err();
+        assert!(refused.is_transient());
+        assert!(!GlossError::NotFound.is_transient());
+    }
+
+    #[test]
+    fn proxy_routing() {
+        let invalid = build_client(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            USER_AGENT,
+            Some("http://[::1"),
+        );
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            "Invalid proxy URL: http://[::1"
+        );
+
+        // Stand in for a proxy, and report what we were asked to do
+        let (proxy, requests) =
+            serve(|_| "502 Bad Gateway\r\nContent-Length: 0\r\n\r\n".to_owned());
+
+        // HTTPS should be tunneled through the proxy, so the request fails
+        let client = build_client(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            USER_AGENT,
+            Some(&proxy),
+        )
+        .unwrap();
+        assert!(get_response_text(&client, &build_url(Source::Tfd, "atavism")).is_err());
 
-        pandoc_primary(etym_mode, &results).unwrap()
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("CONNECT www.thefreedictionary.com:443 "));
     }
 
+    #[test]
+    fn idioms_appended() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>run</h2><div class="pseg"><i>v.</i><div class="ds-list"><b>1. </b>To move swiftly on foot.</div></div><h3>Phrasal Verbs:</h3><div class="pvseg"><b><i>run across</i></b><div class="ds-single">To find by chance.</div></div><h3>Idioms:</h3><div class="idmseg"><b><i>run a fever</i></b><div class="ds-single">To have a fever.</div></div><div class="idmseg"><b><i>run dry</i></b><div class="ds-single">To become dry.</div></div></section></div>"#;
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+
+        // Left out of the usual results...
+        let results = compile_results(Source::Tfd, section_vec.clone(), Sections::First);
+        assert!(!results.contains("run across"));
+
+        // ...but gathered here, in order, each kind under its own heading
+        let idioms = compile_idioms(Source::Tfd, &section_vec, Sections::First);
+        assert_eq!(
+            idioms,
+            r#"<h3>Phrasal Verbs</h3><div class="pvseg"><b><i>run across</i></b><div class="ds-single">To find by chance.</div></div><h3>Idioms</h3><div class="idmseg"><b><i>run a fever</i></b><div class="ds-single">To have a fever.</div></div><div class="idmseg"><b><i>run dry</i></b><div class="ds-single">To become dry.</div></div>"#
+        );
+
+        // An entry with neither has nothing to add
+        let plain_page = r#"<div id="Definition"><section data-src="hm"><h2>at·a·vism</h2><div class="pseg"><i>n.</i></div></section></div>"#;
+        let parsed_chunk = take_chunk(Source::Tfd, plain_page);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+        assert!(compile_idioms(Source::Tfd, &section_vec, Sections::First).is_empty());
+        assert!(compile_idioms(Source::Etymonline, &section_vec, Sections::First).is_empty());
+    }
+
+    #[test]
+    fn user_agent_override() {
+        let invalid = build_client(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            "gloss\nword",
+            None,
+        );
+        assert_eq!(
+            invalid.unwrap_err().to_string(),
+            r#"Invalid user agent: "gloss\nword""#
+        );
+
+        // Stand in for a source site, and report how we introduced ourselves
+        let (base, requests) = serve(|_| "404 Not Found\r\nContent-Length: 0\r\n\r\n".to_owned());
+
+        let client = build_client(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            "Mozilla/5.0 (spoofed)",
+            None,
+        )
+        .unwrap();
+        let _page = get_response_text(&client, &format!("{base}/"));
+
+        let request = requests.recv().unwrap().to_lowercase();
+        assert!(request.contains("\r\nuser-agent: mozilla/5.0 (spoofed)\r\n"));
+    }
+
+    #[test]
+    fn http_status() {
+        // Answer with the given status line (and any headers)
+        let client = test_client();
+        let fetch = |status: &'static str| {
+            let (base, _) = serve(move |_| format!("{status}\r\nContent-Length: 0\r\n\r\n"));
+            get_response_text(&client, &format!("{base}/"))
+        };
+
+        assert!(fetch("200 OK").unwrap().text.is_empty());
+        assert!(matches!(fetch("404 Not Found"), Err(GlossError::NotFound)));
+
+        let forbidden = fetch("403 Forbidden").unwrap_err();
+        assert_eq!(
+            forbidden.to_string(),
+            "Source site returned HTTP 403 Forbidden"
+        );
+        assert!(!forbidden.is_transient());
+
+        let rate_limited = fetch("429 Too Many Requests\r\nRetry-After: 7").unwrap_err();
+        assert!(rate_limited.is_transient());
+        assert_eq!(rate_limited.retry_after(), Some(Duration::from_secs(7)));
+        assert_eq!(
+            rate_limited.to_string(),
+            "Rate limited by source site; slow down (try again in 7 seconds)"
+        );
+
+        let rate_limited = fetch("429 Too Many Requests").unwrap_err();
+        assert!(matches!(rate_limited, GlossError::RateLimited(None)));
+        assert_eq!(
+            rate_limited.to_string(),
+            "Rate limited by source site; slow down"
+        );
+
+        assert!(fetch("503 Service Unavailable").unwrap_err().is_transient());
+    }
+
+    #[cfg(feature = "pandoc")] // Exact Pandoc output
     #[test]
     fn def_atavism() {
-        let etym_mode = false;
-        let lookup_url = "https://www.thefreedictionary.com/atavism";
-        let output = full_sequence(etym_mode, lookup_url);
+        let output = full_sequence(Mode::Definition, "atavism");
 
         let standard = "at·a·vism\n\nn.\n\n1.  The reappearance of a characteristic in an organism after several\n    generations of absence.\n\n2.  An individual or a part that exhibits atavism. Also called\n    throwback.\n\n3.  The return of a trait or recurrence of previous behavior after a\n    period of absence.\n";
 
         assert_eq!(output, standard);
     }
 
+    #[cfg(feature = "pandoc")] // Exact Pandoc output
     #[test]
     fn def_isthmus() {
-        let etym_mode = false;
-        let lookup_url = "https://www.thefreedictionary.com/isthmus";
-        let output = full_sequence(etym_mode, lookup_url);
+        let output = full_sequence(Mode::Definition, "isthmus");
 
         let standard = "isth·mus\n\nn. pl. isth·mus·es or isth·mi (-mī′)\n\n1.  A narrow strip of land connecting two larger masses of land.\n\n2.  Anatomy\n\n    a.  A narrow strip of tissue joining two larger organs or parts of\n        an organ.\n\n    b.  A narrow passage connecting two larger cavities.\n";
 
         assert_eq!(output, standard);
     }
 
+    #[cfg(feature = "pandoc")] // Exact Pandoc output
     #[test]
     fn etym_cummerbund() {
-        let etym_mode = true;
-        let lookup_url = "https://www.etymonline.com/word/cummerbund";
-        let output = full_sequence(etym_mode, lookup_url);
+        let output = full_sequence(Mode::Etymology, "cummerbund");
 
         let standard = "cummerbund (n.)\n\n“large, loose sash worn as a belt,” 1610s, from Hindi kamarband “loin\nband,” from Persian kamar “waist” + band “something that ties,” from\nAvestan banda- “bond, fetter,” from PIE root *bhendh- “to bind.”\n";
 
         assert_eq!(output, standard);
     }
 
+    #[cfg(feature = "pandoc")] // Exact Pandoc output
     #[test]
     fn etym_forest() {
-        let etym_mode = true;
-        let lookup_url = "https://www.etymonline.com/word/forest";
-        let output = full_sequence(etym_mode, lookup_url);
+        let output = full_sequence(Mode::Etymology, "forest");
 
         let standard = "forest (n.)\n\nlate 13c., “extensive tree-covered district,” especially one set aside\nfor royal hunting and under the protection of the king, from Old French\nforest “forest, wood, woodland” (Modern French forêt), probably\nultimately from Late Latin/Medieval Latin forestem silvam “the outside\nwoods,” a term from the Capitularies of Charlemagne denoting “the royal\nforest.” This word comes to Medieval Latin, perhaps via a Germanic\nsource akin to Old High German forst, from Latin foris “outside” (see\nforeign). If so, the sense is “beyond the park,” the park (Latin parcus;\nsee park (n.)) being the main or central fenced woodland.\n\nAnother theory traces it through Medieval Latin forestis, originally\n“forest preserve, game preserve,” from Latin forum in legal sense\n“court, judgment;” in other words “land subject to a ban” [Buck].\nReplaced Old English wudu (see wood (n.)). Spanish and Portuguese\nfloresta have been influenced by flor “flower.”\n\nforest (v.)\n\n“cover with trees or woods,” 1818 (forested is attested from 1610s),\nfrom forest (n.). The earlier word was afforest (c.\u{a0}1500).\n";
 
         assert_eq!(output, standard);
     }
+
+    // Headwords are compared without syllable dots, and with apostrophes folded
+    fn has_headword(text: &str, word: &str) -> bool {
+        normalize_word(&text.replace('·', "")).contains(&normalize_word(word))
+    }
+
+    #[test]
+    #[ignore = "needs network access"]
+    fn def_punctuated() {
+        for word in ["mother-in-law", "o'clock"] {
+            let output = full_sequence(Mode::Definition, word);
+            assert!(has_headword(&output, word), "{word}: {output}");
+        }
+    }
+
+    #[test]
+    #[ignore = "needs network access"]
+    fn etym_punctuated() {
+        for word in ["jack-o'-lantern", "o'clock"] {
+            let output = full_sequence(Mode::Etymology, word);
+            assert!(has_headword(&output, word), "{word}: {output}");
+        }
+    }
+
+    #[test]
+    fn entity_decoding() {
+        assert_eq!(decode_entities("salt &amp; pepper"), "salt & pepper");
+        assert_eq!(decode_entities("c.&#160;1500"), "c.\u{a0}1500");
+        assert_eq!(decode_entities("c.&amp;#160;1500"), "c.\u{a0}1500");
+        assert_eq!(
+            decode_entities("&lt;i&gt; &#x2014; &quot;"),
+            "<i> \u{2014} \""
+        );
+        assert_eq!(
+            decode_entities("&bogus; &#xZZ; & done"),
+            "&bogus; &#xZZ; & done"
+        );
+
+        assert_eq!(ascii_spaces("c.\u{a0}1500"), "c. 1500");
+    }
+
+    #[test]
+    fn headword_removal() {
+        let plain = "at·a·vism\n\nn.\n\n1.  The reappearance of a characteristic.\n\n------\n\nat·a·vism\n\nn.\n\nThe recurrence.\n";
+        assert_eq!(
+            strip_headword(Source::Tfd, Format::Plain, plain),
+            "n.\n\n1.  The reappearance of a characteristic.\n\n------\n\nn.\n\nThe recurrence.\n"
+        );
+
+        let html = "<section><h2>at·a·vism</h2> <i>n.</i></section>\n";
+        assert_eq!(
+            strip_headword(Source::Tfd, Format::Html, html),
+            "<section><i>n.</i></section>\n"
+        );
+
+        let etymology = "forest (n.)\n\nlate 13c.\n";
+        assert_eq!(
+            strip_headword(Source::Etymonline, Format::Plain, etymology),
+            etymology
+        );
+    }
+
+    #[test]
+    fn def_dictionaries() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>at·a·vism</h2></section><section data-src="hc_dict"><h2>atavism</h2></section></div>"#;
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+
+        let headword = |sections: Vec<ElementRef>| sections[0].text().collect::<String>();
+        assert_eq!(
+            headword(get_section_vec(Source::Tfd, &parsed_chunk)),
+            "at·a·vism"
+        );
+        assert_eq!(
+            headword(get_dictionary_sections(&parsed_chunk, Dictionary::Collins)),
+            "atavism"
+        );
+        assert!(get_dictionary_sections(&parsed_chunk, Dictionary::RandomHouse).is_empty());
+    }
+
+    #[test]
+    fn def_notes() {
+        let response_text = r#"<div id="Definition"><section data-src="hm"><h2>at·a·vism</h2><div class="pseg"><i>n.</i><div class="ds-list"><b>1. </b>The reappearance of a characteristic.</div><div class="ds-list"><b>2. </b>An individual that exhibits atavism. Also called <b>throwback</b> or <b>reversion</b>. See <b>heredity</b>.</div></div><div class="usage"><div><b>Usage Note:</b> Atavism is  often confused with <i>anachronism</i>.</div></div></section></div>"#;
+
+        let parsed_chunk = take_chunk(Source::Tfd, response_text);
+        let section_vec = get_section_vec(Source::Tfd, &parsed_chunk);
+
+        assert_eq!(
+            get_also_called(Source::Tfd, &section_vec),
+            ["throwback", "reversion"]
+        );
+        assert_eq!(
+            get_usage_notes(Source::Tfd, &section_vec),
+            ["Atavism is often confused with anachronism."]
+        );
+        assert!(get_also_called(Source::Etymonline, &section_vec).is_empty());
+    }
+
+    #[test]
+    fn redirect_url() {
+        // One request gets sent on to a second, as if to the canonical page
+        let (base, _) = serve(|request| {
+            if request.starts_with("GET /Forest ") {
+                "301 Moved Permanently\r\nLocation: /forest\r\nContent-Length: 0\r\n\r\n"
+            } else {
+                "200 OK\r\nContent-Length: 6\r\n\r\nforest"
+            }
+            .to_owned()
+        });
+        let canonical = format!("{base}/forest");
+
+        let page = get_response_text(&test_client(), &format!("{base}/Forest")).unwrap();
+
+        assert_eq!(page.text, "forest");
+        assert_eq!(page.url.as_str(), canonical);
+    }
+
+    #[test]
+    fn conditional_request() {
+        // Send the page with its validators, then say it's unchanged if they come back
+        let (base, _) = serve(|request| {
+            let request = request.to_lowercase();
+
+            if request.contains("\r\nif-none-match: \"v1\"\r\n")
+                && request.contains("\r\nif-modified-since: wed, 01 jan 2025 00:00:00 gmt\r\n")
+            {
+                "304 Not Modified\r\nContent-Length: 0\r\n\r\n"
+            } else {
+                "200 OK\r\nETag: \"v1\"\r\nLast-Modified: Wed, 01 Jan 2025 00:00:00 GMT\r\nContent-Length: 6\r\n\r\nforest"
+            }
+            .to_owned()
+        });
+        let url = format!("{base}/forest");
+        let client = test_client();
+
+        let page = get_response_text(&client, &url).unwrap();
+        assert_eq!(page.text, "forest");
+        assert_eq!(
+            page.validators,
+            Validators {
+                etag: Some(r#""v1""#.to_owned()),
+                last_modified: Some("Wed, 01 Jan 2025 00:00:00 GMT".to_owned()),
+            }
+        );
+
+        assert!(matches!(
+            get_page(&client, &url, &page.validators),
+            Err(GlossError::NotModified)
+        ));
+    }
+
+    #[test]
+    fn paragraph_rewrap() {
+        let output = "at·a·vism\n\n1.  The reappearance of a characteristic in an organism after several\n    generations of absence.\n\n    a.  A narrow strip of tissue joining two larger organs.\n\nc.\u{a0}1500, from Latin atavus.\n";
+
+        assert_eq!(
+            rewrap(output, 40),
+            "at·a·vism\n\n1.  The reappearance of a characteristic\n    in an organism after several\n    generations of absence.\n\n    a.  A narrow strip of tissue joining\n        two larger organs.\n\nc.\u{a0}1500, from Latin atavus.\n"
+        );
+
+        // Widths are counted in columns, not bytes
+        assert_eq!(rewrap("é é é é", 3), "é é\né é");
+    }
 }
@@ -1,33 +1,180 @@
 #![warn(clippy::pedantic, clippy::nursery, clippy::cargo)]
 
+// The SQLite cache, or stand-ins for it when built without the "cache" feature
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(not(feature = "cache"))]
+#[path = "no_cache.rs"]
+mod cache;
+
+// A stand-in source site, shared with the library's tests
+#[cfg(test)]
+mod test_server;
+
 use core::time::Duration;
-use std::io::Write;
-use std::path::PathBuf;
-use std::process::Command;
-use std::{fs, str};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fmt, fs, mem, process, thread};
 
 use anyhow::{anyhow, Context};
-use clap::{command, Arg, ArgAction};
+use cache::{
+    check_cache_db, evict_cache, export_cache, forget_cached, import_cache, loose_match,
+    open_cache, print_cache_stats, print_history, query_db, query_html, query_not_found,
+    query_validators, random_cached_word, record_history, record_not_found, refresh_cached,
+    store_html, store_validators, update_cache, vacuum_cache, Connection,
+};
+use clap::builder::RangedU64ValueParser;
+use clap::parser::ValueSource;
+use clap::{command, value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap_complete::Shell;
+use console::Term;
 use directories::ProjectDirs;
-use gloss_word::{compile_results, get_response_text, get_section_vec, pandoc_primary, take_chunk};
-use indicatif::{ProgressBar, ProgressStyle};
-use rusqlite::Connection;
-use scraper::{ElementRef, Selector};
-use tempfile::NamedTempFile;
+use gloss_word::{
+    absolutize_links, ascii_spaces, build_client, build_search_url, build_url, build_wotd_url,
+    cache_key, colorize, compile_idioms, compile_results, count_senses, filter_senses, find_chunk,
+    get_also_called, get_audio_urls, get_dictionary_sections, get_examples, get_inflections,
+    get_page, get_pronunciation, get_response_text, get_section_vec, get_suggestions,
+    get_usage_notes, get_word_of_the_day, limit_paragraphs, normalize_word, render_results, rewrap,
+    strip_examples, strip_headword, take_chunk, Dictionary, Format, GlossError, Mode, Page, Region,
+    Renderer, Sections, SenseFilter, Source, Validators, USER_AGENT,
+};
+#[cfg(feature = "pandoc")]
+use gloss_word::{pandoc_command, remove_scratch_dir, set_lossy_output, set_pandoc_path};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use reqwest::blocking::Client;
+use scraper::{ElementRef, Html};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use thiserror::Error;
 
+// Function to define the CLI, for both parsing and completion generation
 #[allow(clippy::too_many_lines)]
-fn main() -> Result<(), anyhow::Error> {
-    //
-    // CLI SETUP
-    //
-
-    let matches = command!()
+fn build_cli() -> Command {
+    command!()
+        .arg(
+            Arg::new("ascii-spaces")
+                .long("ascii-spaces")
+                .help("Print non-breaking spaces as ordinary ones")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("audio")
+                .long("audio")
+                .help("Print the URL of each audio pronunciation, instead of the entry")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["interactive", "json", "offline", "raw-html"]),
+        )
+        .arg(
+            Arg::new("both")
+                .long("both")
+                .help("Look up the definition and then the etymology")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "audio",
+                    "etymology",
+                    "interactive",
+                    "pos",
+                    "sense",
+                    "source",
+                    "suggestions-only",
+                    "word-of-the-day",
+                ]),
+        )
+        .arg(
+            Arg::new("cache-dir")
+                .long("cache-dir")
+                .value_name("PATH")
+                .help("Keep the cache in this directory instead of the default")
+                .env("GLOSS_WORD_CACHE_DIR")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("cache-stats")
+                .long("cache-stats")
+                .help("Show what the cache contains and how large it is")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("cache-misses")
+                .long("cache-misses")
+                .help("Remember words that weren't found, for a day")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("clear-cache")
                 .long("clear-cache")
                 .help("Delete cache directory and its contents")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("clipboard")
+                .long("clipboard")
+                .help("Look up the word (or short phrase) on the clipboard")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("INPUT"),
+        )
+        .arg(
+            Arg::new("compact-cache")
+                .long("compact-cache")
+                .help("Reclaim unused space in the cache database")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("compare-sources")
+                .long("compare-sources")
+                .help("Show the entry from two sources in turn (American Heritage and Collins, or Etymonline and Wiktionary)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "audio",
+                    "both",
+                    "dictionary",
+                    "interactive",
+                    "ipa",
+                    "suggestions-only",
+                ]),
+        )
+        .arg(
+            Arg::new("completions")
+                .long("completions")
+                .value_name("SHELL")
+                .help("Print a completion script for the given shell")
+                .value_parser(value_parser!(Shell)),
+        )
+        .arg(
+            Arg::new("count")
+                .long("count")
+                .help("Print only the number of senses in the definition")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "audio",
+                    "both",
+                    "format",
+                    "json",
+                    "limit",
+                    "raw-html",
+                    "suggestions-only",
+                ]),
+        )
+        .arg(
+            Arg::new("doctor")
+                .long("doctor")
+                .help("Check that Pandoc, the cache, and the source sites all work (for bug reports)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dictionary")
+                .long("dictionary")
+                .value_name("NAME")
+                .help("Show the entry from another of TFD's dictionaries, bypassing the cache [default: american-heritage]")
+                .value_parser(["american-heritage", "collins", "random-house"])
+                .conflicts_with_all(["etymology", "offline", "raw-html"]),
+        )
         .arg(
             Arg::new("etymology")
                 .short('e')
@@ -35,6 +182,49 @@ fn main() -> Result<(), anyhow::Error> {
                 .help("Search for etymology instead of definition")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("examples-only")
+                .long("examples-only")
+                .help("Print only the usage examples, one per line, instead of the entry")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "audio",
+                    "both",
+                    "count",
+                    "etymology",
+                    "format",
+                    "ipa",
+                    "json",
+                    "limit",
+                    "no-examples",
+                    "raw-html",
+                    "suggestions-only",
+                ]),
+        )
+        .arg(
+            Arg::new("export")
+                .long("export")
+                .value_name("PATH")
+                .help("Write all cached entries to a file")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["import", "INPUT"]),
+        )
+        .arg(
+            Arg::new("export-format")
+                .long("export-format")
+                .value_name("FORMAT")
+                .help("File format for --export [default: json]")
+                .value_parser(["json", "csv"])
+                .requires("export")
+                .conflicts_with("INPUT"),
+        )
+        .arg(
+            Arg::new("fallback-wiktionary")
+                .long("fallback-wiktionary")
+                .help("If Etymonline has no entry, try Wiktionary's etymology (never cached)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("offline"),
+        )
         .arg(
             Arg::new("fetch-update")
                 .short('f')
@@ -42,290 +232,3206 @@ fn main() -> Result<(), anyhow::Error> {
                 .help("Fetch new data; update cache if applicable")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("forget")
+                .long("forget")
+                .value_name("WORD")
+                .help("Remove one word from the cache (its etymology, with -e)")
+                .conflicts_with("INPUT"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Print entries as plain text, Markdown, or scraped HTML")
+                .value_parser(["plain", "markdown", "html"])
+                .default_value("plain"),
+        )
+        .arg(
+            Arg::new("full")
+                .long("full")
+                .help("Include every entry on the page, not just the first")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .value_name("N")
+                .help("Show the last N words looked up (default 20)")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .num_args(0..=1)
+                .default_missing_value("20")
+                .conflicts_with("INPUT"),
+        )
+        .arg(
+            Arg::new("import")
+                .long("import")
+                .value_name("PATH")
+                .help("Add entries from an exported file (CSV if it ends in .csv), skipping duplicates")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with("INPUT"),
+        )
+        .arg(
+            Arg::new("interactive")
+                .short('i')
+                .long("interactive")
+                .help(
+                    "Look up words as they're typed, until EOF or \"quit\" (:etym switches modes)",
+                )
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["INPUT", "word-of-the-day"]),
+        )
+        .arg(
+            Arg::new("ipa")
+                .long("ipa")
+                .help("Print only the pronunciation, instead of the entry")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "audio",
+                    "both",
+                    "count",
+                    "etymology",
+                    "format",
+                    "json",
+                    "limit",
+                    "raw-html",
+                    "suggestions-only",
+                ]),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .help("Fetch up to N words at once when reading a list from stdin")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("4"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("Print results as a JSON object")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .value_name("N")
+                .help("Show only the first N paragraphs of an entry")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..)),
+        )
+        .arg(
+            Arg::new("no-cache")
+                .long("no-cache")
+                .help("Neither read from nor write to the cache")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["fetch-update", "offline"]),
+        )
+        .arg(
+            Arg::new("no-color")
+                .long("no-color")
+                .help("Print plain text without highlighting (also via NO_COLOR)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("idioms")
+                .long("idioms")
+                .help("Add any phrasal verbs and idioms after the definition (bypasses the cache)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["etymology", "offline", "raw-html"]),
+        )
+        .arg(
+            Arg::new("no-examples")
+                .long("no-examples")
+                .help("Leave out usage examples, for terser definitions (bypasses the cache)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["offline", "raw-html"]),
+        )
+        .arg(
+            Arg::new("no-headword")
+                .long("no-headword")
+                .help("Leave out the headword (and pronunciation) at the top of a definition")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-pandoc")
+                .long("no-pandoc")
+                .help("Convert entries to plain text without Pandoc (less polished)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pandoc-path")
+                .long("pandoc-path")
+                .value_name("PATH")
+                .env("GLOSS_WORD_PANDOC")
+                .help("Run the Pandoc at this path, instead of the one on the PATH")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("offline")
+                .long("offline")
+                .help("Answer only from the cache; never use the network")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("fetch-update"),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("PATH")
+                .help("Write results to this file, or to one file per word in this directory (ending in /)")
+                .value_parser(value_parser!(PathBuf))
+                .conflicts_with_all(["interactive", "print-url"]),
+        )
+        .arg(
+            Arg::new("pager")
+                .long("pager")
+                .value_name("COMMAND")
+                .help("Page long output with this command [default: $PAGER or less -R]")
+                .conflicts_with("no-pager"),
+        )
+        .arg(
+            Arg::new("no-pager")
+                .long("no-pager")
+                .help("Never page output, even if it's longer than the terminal")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pos")
+                .long("pos")
+                .value_name("PART")
+                .help("Show only senses for this part of speech (e.g. verb, n.)")
+                .conflicts_with("offline"),
+        )
+        .arg(
+            Arg::new("print-url")
+                .long("print-url")
+                .help("Print the URL that would be fetched, and exit (a dry run)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["audio", "interactive", "suggestions-only", "word-of-the-day"]),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("Send requests through this proxy [default: $HTTPS_PROXY or $HTTP_PROXY]"),
+        )
+        .arg(
+            Arg::new("user-agent")
+                .long("user-agent")
+                .value_name("STRING")
+                .env("GLOSS_WORD_UA")
+                .help("Identify ourselves to the source sites as this instead"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Don't show a progress spinner (hidden anyway when stderr isn't a terminal)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("spinner")
+                .long("spinner")
+                .value_name("STYLE")
+                .help("How to draw the progress spinner [default: braille, or ascii if the terminal can't show it]")
+                .value_parser(["braille", "ascii", "none"]),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Mention anything unexpected about the pages fetched")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("quiet"),
+        )
+        .arg(
+            Arg::new("random")
+                .long("random")
+                .help("Look up a random word, from the cache or a built-in list")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "INPUT",
+                    "audio",
+                    "both",
+                    "clipboard",
+                    "interactive",
+                    "print-url",
+                    "suggestions-only",
+                    "word-of-the-day",
+                ]),
+        )
+        .arg(
+            Arg::new("raw-html")
+                .long("raw-html")
+                .help("Print the scraped HTML, bypassing the cache (for debugging)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["format", "json", "offline"]),
+        )
+        .arg(
+            Arg::new("sense")
+                .long("sense")
+                .value_name("N")
+                .help("Show only the Nth sense (of each part of speech)")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                .conflicts_with("offline"),
+        )
+        .arg(
+            Arg::new("show-diff")
+                .long("show-diff")
+                .help("With --fetch-update, show how a cached entry changed instead of the entry")
+                .action(ArgAction::SetTrue)
+                .requires("fetch-update")
+                .conflicts_with_all(["count", "json"]),
+        )
+        .arg(
+            Arg::new("source")
+                .long("source")
+                .value_name("SITE")
+                .help("Site to look words up on [default: tfd, or etymonline with -e]")
+                .value_parser(["tfd", "etymonline"]),
+        )
+        .arg(
+            Arg::new("stdin-html")
+                .long("stdin-html")
+                .help("Render a saved page read from stdin, with no network or cache (for debugging)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all([
+                    "INPUT",
+                    "audio",
+                    "both",
+                    "clipboard",
+                    "interactive",
+                    "print-url",
+                    "random",
+                    "suggestions-only",
+                    "word-of-the-day",
+                ]),
+        )
+        .arg(
+            Arg::new("strict-not-found")
+                .long("strict-not-found")
+                .help("Fail on any miss, instead of offering suggestions (for scripts)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("suggestions-only"),
+        )
+        .arg(
+            Arg::new("suggestions-only")
+                .long("suggestions-only")
+                .help("Print only the \"Did you mean\" list, never an entry (for spell-checking)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["audio", "interactive", "json", "offline", "raw-html"]),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Give up on HTTP requests after this many seconds")
+                .value_parser(value_parser!(u64))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_name("SECONDS")
+                .help("Give up on reaching a host after this many seconds")
+                .value_parser(value_parser!(u64))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("region")
+                .long("region")
+                .value_name("REGION")
+                .help("If a word isn't found, try its spelling in this region (e.g. \"colour\" for uk)")
+                .value_parser(["us", "uk"]),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("N")
+                .help("Retry failed HTTP requests up to this many times")
+                .value_parser(value_parser!(u32))
+                .default_value("2"),
+        )
+        .arg(
+            Arg::new("loose-cache")
+                .long("loose-cache")
+                .help("Let cached entries match regardless of accents (e.g. \"resume\" for \"résumé\")")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("no-cache"),
+        )
+        .arg(
+            Arg::new("lossy")
+                .long("lossy")
+                .help("Replace invalid UTF-8 from Pandoc, instead of failing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-age")
+                .long("max-age")
+                .value_name("DAYS")
+                .help("Refresh cached entries older than this (0 = never)")
+                .value_parser(value_parser!(u32))
+                .default_value("90"),
+        )
+        .arg(
+            Arg::new("max-cache-entries")
+                .long("max-cache-entries")
+                .value_name("N")
+                .help("Keep at most N cached entries, dropping the least recently used (0 = unlimited)")
+                .value_parser(value_parser!(usize))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("min-length")
+                .long("min-length")
+                .value_name("N")
+                .help("Refuse to look up words shorter than N characters, as a guard against typos")
+                .value_parser(value_parser!(usize))
+                .default_value("1"),
+        )
+        .arg(
+            Arg::new("with-links")
+                .long("with-links")
+                .help("Keep cross-references as links to the source site, bypassing the cache (with Markdown or HTML)")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["offline", "raw-html"]),
+        )
+        .arg(
+            Arg::new("wrap")
+                .long("wrap")
+                .value_name("WIDTH")
+                .help("Re-wrap paragraphs to WIDTH columns [default: terminal width]")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(0..))
+                .num_args(0..=1)
+                .default_missing_value("0") // Terminal width
+                .conflicts_with("raw-html"),
+        )
+        .arg(
+            Arg::new("word-of-the-day")
+                .long("word-of-the-day")
+                .help("Look up the source site's word of the day")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["INPUT", "offline"]),
+        )
         .arg(
             Arg::new("INPUT")
-                .help("The word or phrase to look up")
-                .required_unless_present("clear-cache"),
+                .help("The word or phrase to look up (or - to read a list from stdin)")
+                .required_unless_present_any([
+                    "cache-stats",
+                    "clear-cache",
+                    "clipboard",
+                    "compact-cache",
+                    "completions",
+                    "doctor",
+                    "export",
+                    "forget",
+                    "history",
+                    "import",
+                    "interactive",
+                    "random",
+                    "stdin-html",
+                    "word-of-the-day",
+                ]),
         )
-        .get_matches();
+}
+
+// The spinner currently on screen, if any, so that Ctrl-C can clear it
+static ACTIVE_SPINNER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+// Where results go: stdout, a file given with --output, or a pager
+// Pager output is held until the end, when we know whether it fits on screen
+enum Sink {
+    Stdout,
+    File(fs::File),
+    Pager { command: String, buffer: String },
+}
+
+static OUTPUT: Mutex<Sink> = Mutex::new(Sink::Stdout);
+
+// Like print! and println!, but to the output file or pager if there is one
+macro_rules! out {
+    ($($arg:tt)*) => {
+        write_output(format_args!($($arg)*))
+    };
+}
+
+macro_rules! outln {
+    () => {
+        out!("\n")
+    };
+    ($($arg:tt)*) => {
+        out!("{}\n", format_args!($($arg)*))
+    };
+}
+
+// Function to write results where they're meant to go
+fn write_output(args: fmt::Arguments) -> io::Result<()> {
+    match &mut *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) {
+        Sink::Stdout => io::stdout().write_fmt(args),
+        Sink::File(file) => file.write_fmt(args),
+        Sink::Pager { buffer, .. } => {
+            fmt::Write::write_fmt(buffer, args).map_err(|_| io::Error::other("Formatting failed"))
+        }
+    }
+}
+
+// Function to show whatever the pager has been holding, through the pager if it's too long
+// If the pager won't start, the output goes straight to stdout after all
+fn finish_output() -> io::Result<()> {
+    let sink = mem::replace(
+        &mut *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner),
+        Sink::Stdout,
+    );
+
+    let Sink::Pager { command, buffer } = sink else {
+        return Ok(());
+    };
+
+    let (rows, _) = Term::stdout().size();
+    if buffer.lines().count() < usize::from(rows) {
+        return io::stdout().write_all(buffer.as_bytes());
+    }
+
+    let mut parts = command.split_whitespace();
+    let pager = parts.next().and_then(|program| {
+        process::Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .spawn()
+            .ok()
+    });
+
+    let Some(mut pager) = pager else {
+        return io::stdout().write_all(buffer.as_bytes());
+    };
+
+    // The user may quit the pager before reading everything; that's fine
+    if let Some(mut stdin) = pager.stdin.take() {
+        let _write = stdin.write_all(buffer.as_bytes());
+    }
+
+    pager.wait().map(|_| ())
+}
+
+// Function to choose a pager command: the flag's, else $PAGER's, else less
+// An empty command (or cat) means no pager at all
+fn pager_command(flag: Option<&str>, env_pager: Option<String>) -> Option<String> {
+    let command = flag
+        .map(str::to_owned)
+        .or(env_pager)
+        .unwrap_or_else(|| "less -R".to_owned());
+
+    Some(command).filter(|command| !matches!(command.trim(), "" | "cat"))
+}
+
+// Function to send results to a file from now on, creating its directory if need be
+fn open_output(path: &Path) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+    }
+
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to open {} for writing", path.display()))?;
+
+    *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) = Sink::File(file);
+    Ok(())
+}
+
+// Function to name a word's file in an output directory, e.g. atavism.md
+fn word_file(dir: &Path, word: &str, output: Output, format: Format) -> PathBuf {
+    let extension = match (output, format) {
+        (Output::Json, _) => "json",
+        (_, Format::Markdown) => "md",
+        (_, Format::Html) => "html",
+        (_, Format::Plain) => "txt",
+    };
+
+    dir.join(format!("{}.{extension}", word.replace(['/', '\\'], "_")))
+}
+
+// Exit codes, so that scripts can tell what went wrong
+const EXIT_ERROR: i32 = 1;
+const EXIT_NOT_FOUND: i32 = 2;
+const EXIT_NETWORK: i32 = 3;
+const EXIT_PANDOC: i32 = 4;
+
+fn main() {
+    let result = set_interrupt_handler().and_then(|()| run());
+    let _paged = finish_output();
+    #[cfg(feature = "pandoc")]
+    remove_scratch_dir();
+
+    if let Err(e) = result {
+        eprintln!("Error: {e:?}");
+        process::exit(exit_code(&e));
+    }
+}
+
+// Pick an exit code according to the first error we recognize in the chain
+fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if cause.is::<NotFound>() {
+            return EXIT_NOT_FOUND;
+        }
+
+        if let Some(gloss_error) = cause.downcast_ref::<GlossError>() {
+            return match gloss_error {
+                GlossError::NotFound => EXIT_NOT_FOUND,
+                GlossError::Timeout(_)
+                | GlossError::Http { .. }
+                | GlossError::Proxy { .. }
+                | GlossError::Status(_)
+                | GlossError::RateLimited(_) => EXIT_NETWORK,
+                GlossError::PandocMissing
+                | GlossError::PandocPath { .. }
+                | GlossError::PandocFailed(_)
+                | GlossError::PandocOutput(_) => EXIT_PANDOC,
+                _ => EXIT_ERROR,
+            };
+        }
+    }
+
+    EXIT_ERROR
+}
+
+// On Ctrl-C, tidy up the terminal and any tempfiles before exiting
+fn set_interrupt_handler() -> Result<(), anyhow::Error> {
+    ctrlc::set_handler(|| {
+        if let Some(pb) = ACTIVE_SPINNER
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+        {
+            pb.finish_and_clear();
+        }
+
+        let _cursor = Term::stderr().show_cursor();
+        #[cfg(feature = "pandoc")]
+        remove_scratch_dir();
+        process::exit(130);
+    })
+    .context("Failed to set Ctrl-C handler")
+}
+
+#[allow(clippy::too_many_lines)]
+fn run() -> Result<(), anyhow::Error> {
+    //
+    // CLI SETUP
+    //
+
+    let matches = build_cli().get_matches();
+
+    // If we were asked for shell completions, print them and return
+    if let Some(&shell) = matches.get_one::<Shell>("completions") {
+        // The package is gloss-word, but the command is gloss
+        let bin_name = env!("CARGO_BIN_NAME");
+        clap_complete::generate(shell, &mut build_cli(), bin_name, &mut io::stdout());
+        return Ok(());
+    }
+
+    // Settings from the config file fill in for flags not given
+    let config = ProjectDirs::from("com", "theobeers", "gloss-word")
+        .map(|proj_dirs| load_config(&proj_dirs.config_dir().join("config.toml")))
+        .unwrap_or_default();
+
+    //
+    // "GLOBAL" VARIABLES
+    //
+
+    // Do we have flags?
+    let audio = matches.get_flag("audio");
+    let both = matches.get_flag("both");
+    let cache_misses = matches.get_flag("cache-misses");
+    let cache_stats = matches.get_flag("cache-stats");
+    let clear_cache = matches.get_flag("clear-cache");
+    let compact_cache = matches.get_flag("compact-cache");
+    let compare = matches.get_flag("compare-sources");
+    let count = matches.get_flag("count");
+    let examples_only = matches.get_flag("examples-only");
+    let fallback_wiktionary = matches.get_flag("fallback-wiktionary");
+    let force_fetch = matches.get_flag("fetch-update");
+    let interactive = matches.get_flag("interactive");
+    let ipa = matches.get_flag("ipa");
+    let json_output = matches.get_flag("json");
+    let updates = if matches.get_flag("show-diff") {
+        Updates::Diff
+    } else {
+        Updates::Print
+    };
+    let spaces = if matches.get_flag("ascii-spaces") {
+        Spaces::Ascii
+    } else {
+        Spaces::AsRendered
+    };
+    let loose_cache = matches.get_flag("loose-cache");
+    if matches.get_flag("lossy") && !cfg!(feature = "pandoc") {
+        return Err(anyhow!(
+            "--lossy applies only to Pandoc (this build lacks the \"pandoc\" feature)"
+        ));
+    }
+    #[cfg(feature = "pandoc")]
+    set_lossy_output(matches.get_flag("lossy"));
+    if let Some(pandoc_path) = matches.get_one::<PathBuf>("pandoc-path") {
+        #[cfg(feature = "pandoc")]
+        set_pandoc_path(pandoc_path)?;
+
+        #[cfg(not(feature = "pandoc"))]
+        return Err(anyhow!(
+            "Can't run the Pandoc at {} (this build lacks the \"pandoc\" feature)",
+            pandoc_path.display()
+        ));
+    }
+    let no_cache = matches.get_flag("no-cache");
+    let no_color = matches.get_flag("no-color") || config.color == Some(false);
+    let output_path = matches.get_one::<PathBuf>("output");
+    let no_examples = matches.get_flag("no-examples");
+    let with_idioms = matches.get_flag("idioms");
+    let no_headword = matches.get_flag("no-headword");
+    let no_pandoc = matches.get_flag("no-pandoc");
+    let offline = matches.get_flag("offline");
+    let print_url = matches.get_flag("print-url");
+    let quiet = matches.get_flag("quiet");
+    let spinner_style = match matches.get_one::<String>("spinner").map(String::as_str) {
+        Some("braille") => Some(SpinnerStyle::Braille),
+        Some("ascii") => Some(SpinnerStyle::Ascii),
+        Some("none") => Some(SpinnerStyle::Hidden),
+        Some(_) => unreachable!(), // Checked by clap
+        None => config.spinner,
+    };
+    let random = matches.get_flag("random");
+    let verbose = matches.get_flag("verbose");
+    let raw_html = matches.get_flag("raw-html");
+    let stdin_html = matches.get_flag("stdin-html");
+    let strict_not_found = matches.get_flag("strict-not-found");
+    let suggestions_only = matches.get_flag("suggestions-only");
+    let with_links = matches.get_flag("with-links");
+    let word_of_the_day = matches.get_flag("word-of-the-day");
+
+    // What are we looking for? A --source on the command line outranks the config
+    let mode = if matches.get_flag("etymology") {
+        Mode::Etymology
+    } else if matches.contains_id("source") {
+        Mode::Definition
+    } else {
+        config.mode.unwrap_or(Mode::Definition)
+    };
+
+    // Which site should we ask? Each provides only one kind of entry
+    let source = match matches.get_one::<String>("source").map(String::as_str) {
+        Some("tfd") => Source::Tfd,
+        Some("etymonline") => Source::Etymonline,
+        Some(_) => unreachable!(), // Checked by clap
+        None => Source::default_for(mode),
+    };
+
+    if source.mode() != mode {
+        let wanted = match mode {
+            Mode::Definition => "definitions",
+            Mode::Etymology => "etymologies",
+        };
+        return Err(anyhow!("{} does not provide {wanted}", source.name()));
+    }
+
+    // Is there another site to try? Only Wiktionary, for now
+    let fallback_source = fallback_wiktionary.then_some(Source::Wiktionary);
+
+    if fallback_source.is_some_and(|fallback| fallback.mode() != mode) {
+        return Err(anyhow!("--fallback-wiktionary applies only to etymologies"));
+    }
+
+    if audio && source != Source::Tfd {
+        return Err(anyhow!("{} has no audio pronunciations", source.name()));
+    }
+
+    // How long should we wait for the network?
+    let timeout = Duration::from_secs(or_config(&matches, "timeout", config.timeout));
+    let connect_timeout = Duration::from_secs(or_config(
+        &matches,
+        "connect-timeout",
+        config.connect_timeout,
+    ));
+    let retries = or_config(&matches, "retries", config.retries);
+
+    // How long do cached entries stay fresh? (In seconds; 0 means forever)
+    let max_age = i64::from(or_config(&matches, "max-age", config.max_age)) * 86_400;
+
+    // How many entries may the cache hold? (0 means no limit)
+    let max_entries = or_config(&matches, "max-cache-entries", config.max_cache_entries);
+
+    // How short a word is too short to bother with? By default, only an empty one
+    let min_length = or_config(&matches, "min-length", config.min_length);
+
+    // What should entries look like? Raw HTML is just HTML, uncached
+    // Senses are counted (and examples found) in HTML, which is cached separately
+    // A pronunciation comes with the plain-text entry (HTML leaves it in place)
+    let format = match matches.get_one::<String>("format").map(String::as_str) {
+        _ if raw_html || count || examples_only => Format::Html,
+        _ if ipa => Format::Plain,
+        _ if !from_command_line(&matches, "format") && config.format.is_some() => {
+            config.format.unwrap_or(Format::Plain)
+        }
+        Some("markdown") => Format::Markdown,
+        Some("html") => Format::Html,
+        _ => Format::Plain, // Has default
+    };
+
+    // Who does the conversion? Without Pandoc, Markdown is out of reach
+    let renderer = if no_pandoc {
+        Renderer::Builtin
+    } else {
+        Renderer::default()
+    };
+
+    if renderer == Renderer::Builtin && !cfg!(feature = "render") {
+        return Err(anyhow!(
+            "--no-pandoc needs the built-in renderer (this build lacks the \"render\" feature)"
+        ));
+    }
+
+    if renderer == Renderer::Builtin && format == Format::Markdown {
+        return Err(anyhow!("Markdown output requires Pandoc"));
+    }
+
+    // Must a cached entry be for exactly this word? By default, yes
+    let cache_match = if loose_cache {
+        CacheMatch::Loose
+    } else {
+        CacheMatch::Exact
+    };
+
+    // Should cross-references stay links? Plain text has no room for them
+    let links = if with_links {
+        Links::Absolute
+    } else {
+        Links::AsScraped
+    };
+
+    if links == Links::Absolute && format == Format::Plain {
+        return Err(anyhow!("--with-links requires --format markdown or html"));
+    }
+
+    // Should a miss be softened with suggestions? Not if a script needs a clear answer
+    let misses = if strict_not_found {
+        Misses::Fail
+    } else {
+        Misses::Suggest
+    };
+
+    // Should definitions start with the headword? Usually, but not on a flashcard
+    let headwords = if no_headword {
+        Headwords::Strip
+    } else {
+        Headwords::Keep
+    };
+
+    // Should usage examples be kept? Etymologies have none to leave out
+    let examples = if no_examples {
+        Examples::Strip
+    } else {
+        Examples::Keep
+    };
+
+    // Should phrasal verbs and idioms follow? Only if asked; they can run long
+    let idioms = if with_idioms {
+        Idioms::Append
+    } else {
+        Idioms::Omit
+    };
+
+    // How many pages can we fetch at once, in batch mode?
+    let jobs = *matches.get_one::<usize>("jobs").unwrap(); // Has default
+
+    // Should long entries be cut short?
+    let limit = matches.get_one::<usize>("limit").copied();
+
+    // Should paragraphs be re-wrapped? With no width given, to fit the terminal (or 80)
+    let wrap = matches.get_one::<usize>("wrap").map(|&width| match width {
+        0 => Term::stdout()
+            .size_checked()
+            .map_or(80, |(_, columns)| usize::from(columns)),
+        width => width,
+    });
+
+    if wrap.is_some() && format == Format::Html {
+        return Err(anyhow!("--wrap applies only to plain text and Markdown"));
+    }
+
+    // Should we take every entry on the page, or only the first?
+    let sections = if matches.get_flag("full") {
+        Sections::All
+    } else {
+        Sections::First
+    };
+
+    // Should we narrow a definition down? Only TFD entries can be narrowed
+    let senses = SenseFilter {
+        pos: matches.get_one::<String>("pos").cloned(),
+        sense: matches.get_one::<usize>("sense").copied(),
+    };
+
+    if mode == Mode::Etymology && !senses.is_empty() {
+        return Err(anyhow!("--pos and --sense apply only to definitions"));
+    }
+
+    // Which of TFD's dictionaries should we take the entry from?
+    let dictionary = match matches.get_one::<String>("dictionary").map(String::as_str) {
+        Some("collins") => Dictionary::Collins,
+        Some("random-house") => Dictionary::RandomHouse,
+        _ => Dictionary::AmericanHeritage,
+    };
+
+    if mode == Mode::Etymology && dictionary != Dictionary::default() {
+        return Err(anyhow!("--dictionary applies only to definitions"));
+    }
+
+    // Should we explain ourselves along the way?
+    let verbosity = if verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+
+    // Should we try another spelling before giving up?
+    let region = match matches.get_one::<String>("region").map(String::as_str) {
+        Some("us") => Some(Region::Us),
+        Some("uk") => Some(Region::Uk),
+        Some(_) => unreachable!(), // Checked by clap
+        None => None,
+    };
+
+    // How should results be printed?
+    let output = if count {
+        Output::Count
+    } else if ipa {
+        Output::Pronunciation
+    } else if examples_only {
+        Output::Examples
+    } else {
+        Output::choose(json_output, no_color || output_path.is_some(), format)
+    };
+
+    if output == Output::Count && mode == Mode::Etymology {
+        return Err(anyhow!("--count applies only to definitions"));
+    }
+
+    if output == Output::Pronunciation && mode == Mode::Etymology {
+        return Err(anyhow!("--ipa applies only to definitions"));
+    }
+
+    if output == Output::Examples && mode == Mode::Etymology {
+        return Err(anyhow!("--examples-only applies only to definitions"));
+    }
+
+    // Should we show that we're working? Not if no one's watching
+    // And if so, how? Braille only if the terminal looks like it can draw it
+    let spinner_style = spinner_style.unwrap_or_else(|| {
+        if unicode_terminal() {
+            SpinnerStyle::Braille
+        } else {
+            SpinnerStyle::Ascii
+        }
+    });
+
+    let progress = if quiet || spinner_style == SpinnerStyle::Hidden || !io::stderr().is_terminal()
+    {
+        Progress::Hidden
+    } else {
+        Progress::Spinner {
+            ticks: tick_strings(spinner_style, config.spinner_ticks.as_deref()),
+            message: config
+                .spinner_message
+                .unwrap_or_else(|| "Fetching...".to_owned()),
+        }
+    };
+
+    // A proxy can be given explicitly, or in the usual environment variables
+    let proxy = matches.get_one::<String>("proxy").cloned().or_else(|| {
+        ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]
+            .into_iter()
+            .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+    });
+
+    // The user agent can be overridden, on the command line or in the environment
+    let user_agent = matches
+        .get_one::<String>("user-agent")
+        .map_or(USER_AGENT, String::as_str);
+
+    // Take input and normalize it (lowercase, with tidy whitespace)
+    let desired_word = if matches.get_flag("clipboard") {
+        read_clipboard()?
+    } else {
+        matches
+            .get_one::<String>("INPUT")
+            .map_or_else(String::new, |input_word| normalize_word(input_word)) // Empty only for cache flags
+    };
+
+    // For a dry run, show where we would go, and go nowhere (not even the cache)
+    if print_url {
+        let words = if desired_word == "-" {
+            read_words()?
+        } else {
+            vec![desired_word]
+        };
+
+        for word in words {
+            check_word(&word, min_length)?;
+            println!("{}", build_url(source, &word));
+        }
+        return Ok(());
+    }
+
+    // What will be the path to the cache db, if any?
+    let mut db_path: Option<PathBuf> = None;
+
+    //
+    // CACHE DIRECTORY
+    //
+
+    // A custom location takes precedence over the platform default
+    let cache_dir: Option<PathBuf> =
+        matches
+            .get_one::<PathBuf>("cache-dir")
+            .cloned()
+            .or_else(|| {
+                ProjectDirs::from("com", "theobeers", "gloss-word")
+                    .map(|proj_dirs| proj_dirs.cache_dir().to_owned())
+            });
+
+    // A checkup, rather than a lookup
+    if matches.get_flag("doctor") {
+        let client = build_client(timeout, connect_timeout, user_agent, proxy.as_deref())?;
+        return run_doctor(cache_dir.as_deref(), &client);
+    }
+
+    // Most operations here can fail silently; caching is optional
+    if let Some(cache_dir) = cache_dir {
+        // If we have clear-cache flag, handle it and return
+        if clear_cache {
+            if !cache_dir.exists() {
+                return Err(anyhow!("Cache directory not found"));
+            }
+
+            trash::delete(&cache_dir)?;
+            eprintln!("Cache directory deleted");
+            return Ok(());
+        }
+
+        // If we don't have the cache dir yet, try to create it (if we'll use it)
+        if cfg!(feature = "cache") && !cache_dir.exists() {
+            let _dir = fs::create_dir_all(&cache_dir);
+        }
+
+        // Construct appropriate path for db
+        let cache_db = cache_dir.join("entries.sqlite");
+
+        // If we have cache-stats flag, handle it and return
+        if cache_stats {
+            return print_cache_stats(&cache_db);
+        }
+
+        // Likewise for forgetting a single word
+        if let Some(word) = matches.get_one::<String>("forget") {
+            return forget_cached(&cache_db, &normalize_word(word), mode);
+        }
+
+        // Likewise for compaction
+        if compact_cache {
+            let (before, after) = vacuum_cache(&cache_db)?;
+            eprintln!(
+                "Cache database compacted from {} to {}",
+                HumanBytes(before),
+                HumanBytes(after)
+            );
+            return Ok(());
+        }
+
+        // Likewise for history
+        if let Some(&count) = matches.get_one::<usize>("history") {
+            return print_history(&cache_db, count);
+        }
+
+        // Likewise for moving entries in or out of the cache
+        if let Some(export_path) = matches.get_one::<PathBuf>("export") {
+            let csv = matches
+                .get_one::<String>("export-format")
+                .is_some_and(|format| format == "csv");
+            return export_cache(&cache_db, export_path, csv);
+        }
+
+        if let Some(import_path) = matches.get_one::<PathBuf>("import") {
+            return import_cache(&cache_db, import_path);
+        }
+
+        // Unless the cache is to be left alone entirely
+        // Partial, expanded, or linked entries aren't cached either, so as not to pass for standard ones
+        if !no_cache
+            && !raw_html
+            && !stdin_html
+            && senses.is_empty()
+            && sections == Sections::First
+            && links == Links::AsScraped
+            && examples == Examples::Keep
+            && idioms == Idioms::Omit
+            && dictionary == Dictionary::default()
+        {
+            db_path = Some(cache_db);
+        }
+    }
+
+    //
+    // LOOKUP(S)
+    //
+
+    // One connection serves every lookup; again, this can fail silently
+    let db_conn = db_path.as_deref().and_then(open_cache);
+
+    let mut session = Session {
+        cache_match,
+        cache_misses,
+        client: build_client(timeout, connect_timeout, user_agent, proxy.as_deref())?,
+        db_conn,
+        dictionary,
+        examples,
+        fallback_source,
+        force_fetch,
+        format,
+        headwords,
+        idioms,
+        limit,
+        links,
+        max_age,
+        max_entries,
+        min_length,
+        misses,
+        offline,
+        output,
+        prefetched: RefCell::new(HashMap::new()),
+        spaces,
+        updates,
+        progress,
+        region,
+        renderer,
+        retries,
+        sections,
+        senses,
+        source,
+        verbosity,
+        wrap,
+    };
+
+    // Results can go to a file, or to a directory of them, one per word
+    let output_dir = match output_path {
+        Some(path) if path.is_dir() || path.to_string_lossy().ends_with(['/', '\\']) => {
+            Some(path.as_path())
+        }
+        Some(path) => {
+            open_output(path)?;
+            None
+        }
+        None => None,
+    };
+
+    // Long output goes through a pager, but only for a person at a terminal reading one entry
+    // A batch (or JSON) is printed as it comes, rather than held until the last word
+    if output_path.is_none()
+        && !interactive
+        && !json_output
+        && desired_word != "-"
+        && !matches.get_flag("no-pager")
+        && io::stdout().is_terminal()
+    {
+        let flag = matches.get_one::<String>("pager").map(String::as_str);
+
+        if let Some(command) = pager_command(flag, env::var("PAGER").ok()) {
+            *OUTPUT.lock().unwrap_or_else(PoisonError::into_inner) = Sink::Pager {
+                command,
+                buffer: String::new(),
+            };
+        }
+    }
+
+    // A word's own file needs no heading, nor does JSON
+    let headings = !json_output && output_dir.is_none();
+
+    // A page given on stdin goes through the pipeline as if fetched, with no word or URL
+    if stdin_html {
+        if output_dir.is_some() {
+            return Err(anyhow!(
+                "--stdin-html takes a file for --output, not a directory"
+            ));
+        }
+
+        let page = io::read_to_string(io::stdin()).context("Failed to read stdin")?;
+        let (entry, headword) = render_page(&session, &page)?;
+
+        return print_entry(
+            "",
+            source,
+            "",
+            false,
+            &session.finish_entry(source, &entry),
+            &session.finish_headword(headword),
+            session.output,
+        );
+    }
+
+    // The word of the day goes through the usual pipeline, under its own heading
+    if word_of_the_day {
+        let word = fetch_word_of_the_day(&session)?;
+
+        if let Some(dir) = output_dir {
+            open_output(&word_file(dir, &word, output, format))?;
+        }
+
+        if headings {
+            outln!("=== {word} ===\n")?;
+        }
+
+        return look_up(&session, &word);
+    }
+
+    // Likewise for a random word, flashcard style
+    if random {
+        let word = random_word(session.db_conn.as_ref(), mode, format);
+
+        if let Some(dir) = output_dir {
+            open_output(&word_file(dir, &word, output, format))?;
+        }
+
+        if headings {
+            outln!("=== {word} ===\n")?;
+        }
+
+        return look_up(&session, &word);
+    }
+
+    // Any other single word gets its file right away
+    if let Some(dir) = output_dir.filter(|_| desired_word != "-") {
+        open_output(&word_file(dir, &desired_word, output, format))?;
+    }
+
+    // Audio is never cached, so go straight to the page
+    if audio {
+        return print_audio_urls(&session, &desired_word);
+    }
+
+    // Likewise for suggestions
+    if suggestions_only {
+        return print_suggestions(&session, &desired_word);
+    }
+
+    // Both kinds of entry, one after the other
+    if both {
+        if desired_word == "-" {
+            return Err(anyhow!("--both takes a single word, not a list"));
+        }
+
+        return look_up_both(&mut session, &desired_word);
+    }
+
+    // Likewise the entries from two sources
+    if compare {
+        if desired_word == "-" {
+            return Err(anyhow!("--compare-sources takes a single word, not a list"));
+        }
+
+        return compare_sources(&mut session, &desired_word);
+    }
+
+    // In interactive mode, the session lasts as long as the user wants
+    if interactive {
+        return repl(&mut session);
+    }
+
+    // With only one word, any failure is the final result
+    if desired_word != "-" {
+        return look_up(&session, &desired_word);
+    }
+
+    // Otherwise read a list from stdin; one miss shouldn't sink the batch
+    let words = read_words()?;
+
+    // Fetch in parallel, if we can, then go through the words in order
+    if jobs > 1 && !offline {
+        prefetch(&session, &words, jobs);
+    }
+
+    for word in words {
+        // In a directory, each word has a file to itself, and failures go to stderr
+        if let Some(dir) = output_dir {
+            open_output(&word_file(dir, &word, output, format))?;
+
+            if let Err(e) = look_up(&session, &word) {
+                eprintln!("{word}: {e}");
+            }
+            continue;
+        }
+
+        // JSON Lines: one self-contained object per word, errors included
+        if json_output {
+            if let Err(e) = look_up(&session, &word) {
+                print_error_json(&word, source, &e)?;
+            }
+            continue;
+        }
+
+        outln!("=== {word} ===\n")?;
+
+        if let Err(e) = look_up(&session, &word) {
+            outln!("{e}")?;
+        }
+
+        outln!()?;
+    }
+
+    Ok(())
+}
+
+// Function to read a list of words from stdin, one per line, skipping blanks
+fn read_words() -> Result<Vec<String>, anyhow::Error> {
+    io::stdin()
+        .lock()
+        .lines()
+        .map(|line| line.map(|line| normalize_word(&line)))
+        .filter(|word| word.as_ref().map_or(true, |word| !word.is_empty()))
+        .collect::<Result<_, _>>()
+        .context("Failed to read from stdin")
+}
+
+// Function to get a word to look up from the clipboard
+fn read_clipboard() -> Result<String, anyhow::Error> {
+    let text = match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+        Ok(text) => text,
+        Err(arboard::Error::ContentNotAvailable) => {
+            return Err(anyhow!("Clipboard is empty or doesn't contain text"))
+        }
+        Err(e) => return Err(e).context("Failed to read clipboard"),
+    };
+
+    clipboard_word(&text).ok_or_else(|| anyhow!("Clipboard is empty or doesn't contain text"))
+}
+
+// Pick out what to look up from a selection, which may be sloppy
+// A few plain words (like "ice cream" or "coup d'état") are kept as a phrase;
+// anything longer or with other punctuation gets cut to its first word
+fn clipboard_word(text: &str) -> Option<String> {
+    let is_word = |word: &str| {
+        word.chars()
+            .all(|c| c.is_alphabetic() || matches!(c, '-' | '\'' | '’'))
+    };
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() <= 3 && words.iter().all(|word| is_word(word)) {
+        return Some(normalize_word(text)).filter(|word| !word.is_empty());
+    }
+
+    // Otherwise, the first word, without any quotes or punctuation around it
+    let first = words.first()?.trim_matches(|c: char| !c.is_alphanumeric());
+
+    Some(normalize_word(first)).filter(|word| !word.is_empty())
+}
+
+// Defaults from config.toml, in the platform's config directory
+// Any of these can be overridden by the corresponding flag
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct Config {
+    mode: Option<Mode>,
+    format: Option<Format>,
+    color: Option<bool>,
+    timeout: Option<u64>,
+    connect_timeout: Option<u64>,
+    retries: Option<u32>,
+    max_age: Option<u32>,
+    max_cache_entries: Option<usize>,
+    min_length: Option<usize>,
+    spinner: Option<SpinnerStyle>,
+    spinner_ticks: Option<String>,
+    spinner_message: Option<String>,
+}
+
+// Function to read the config file; problems are worth a warning, but no more
+fn load_config(path: &Path) -> Config {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Config::default(),
+        Err(e) => {
+            eprintln!("Warning: failed to read {}: {e}", path.display());
+            return Config::default();
+        }
+    };
+
+    toml::from_str(&text).unwrap_or_else(|e| {
+        eprintln!("Warning: ignoring malformed {}: {e}", path.display());
+        Config::default()
+    })
+}
+
+// Was an argument actually given, as opposed to defaulted?
+fn from_command_line(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+// Take an argument's value if given, else the config's, else the argument's default
+fn or_config<T: Clone + Send + Sync + 'static>(
+    matches: &ArgMatches,
+    id: &str,
+    config_value: Option<T>,
+) -> T {
+    config_value
+        .filter(|_| !from_command_line(matches, id))
+        .unwrap_or_else(|| matches.get_one::<T>(id).cloned().unwrap()) // Has default
+}
+
+// Everything a lookup needs to know, shared across a batch
+struct Session {
+    cache_match: CacheMatch,
+    cache_misses: bool,
+    client: Client,
+    db_conn: Option<Connection>,
+    dictionary: Dictionary,
+    examples: Examples,
+    fallback_source: Option<Source>,
+    force_fetch: bool,
+    format: Format,
+    headwords: Headwords,
+    idioms: Idioms,
+    limit: Option<usize>,
+    links: Links,
+    max_age: i64,
+    max_entries: usize,
+    min_length: usize,
+    misses: Misses,
+    offline: bool,
+    output: Output,
+    // Pages fetched ahead of time for a batch, by URL
+    prefetched: RefCell<HashMap<String, Result<Page, GlossError>>>,
+    spaces: Spaces,
+    updates: Updates,
+    progress: Progress,
+    region: Option<Region>,
+    renderer: Renderer,
+    retries: u32,
+    sections: Sections,
+    senses: SenseFilter,
+    source: Source,
+    verbosity: Verbosity,
+    wrap: Option<usize>,
+}
+
+impl Session {
+    // Cut an entry down to size and tidy it up (headword, spaces, wrapping), if asked to
+    // The cache always keeps it whole, as rendered
+    fn finish_entry(&self, source: Source, body: &str) -> String {
+        let body = match self.headwords {
+            Headwords::Keep => body.to_owned(),
+            Headwords::Strip => strip_headword(source, self.format, body),
+        };
+
+        let body = self
+            .limit
+            .map_or_else(|| body.clone(), |limit| limit_paragraphs(&body, limit));
+
+        let body = match self.spaces {
+            Spaces::AsRendered => body,
+            Spaces::Ascii => ascii_spaces(&body),
+        };
+
+        self.wrap
+            .map_or_else(|| body.clone(), |width| rewrap(&body, width))
+    }
+
+    // Compile a page's sections into an entry's HTML, keeping only what was asked for
+    // Set aside the pronunciation (unless HTML, left as scraped), any inflections, and notes
+    fn compile_entry(
+        &self,
+        source: Source,
+        section_vec: &[ElementRef],
+    ) -> Result<(String, Headword), anyhow::Error> {
+        let headword = Headword {
+            pronunciation: get_pronunciation(source, section_vec)
+                .filter(|_| self.format != Format::Html),
+            inflections: get_inflections(source, section_vec),
+            also_called: get_also_called(source, section_vec),
+            usage_notes: get_usage_notes(source, section_vec),
+        };
+
+        let mut results = if self.senses.is_empty() {
+            compile_results(source, section_vec.to_vec(), self.sections)
+        } else {
+            filter_senses(source, section_vec, self.sections, &self.senses)?
+        };
+
+        // Phrasal verbs and idioms come last, if wanted (and if there are any)
+        if self.idioms == Idioms::Append {
+            results.push_str(&compile_idioms(source, section_vec, self.sections));
+        }
+
+        let results = match self.examples {
+            Examples::Keep => results,
+            Examples::Strip => strip_examples(source, &results),
+        };
+
+        let results = match self.links {
+            Links::AsScraped => results,
+            Links::Absolute => absolutize_links(source, &results),
+        };
+
+        Ok((results, headword))
+    }
+
+    // Render compiled HTML in the chosen format, by Pandoc unless we can do without
+    fn render(&self, source: Source, results: &str) -> Result<String, GlossError> {
+        render_results(source, self.format, self.renderer, results)
+    }
+
+    // Without the headword line, a pronunciation would have nowhere to go (except in JSON)
+    fn finish_headword(&self, headword: Headword) -> Headword {
+        match (self.headwords, self.output) {
+            (Headwords::Strip, Output::Colored | Output::Plain) => Headword {
+                pronunciation: None,
+                ..headword
+            },
+            _ => headword,
+        }
+    }
+
+    // Mention something on stderr, if asked to (without garbling the spinner)
+    fn note(&self, pb: &ProgressBar, message: &str) {
+        if self.verbosity == Verbosity::Verbose {
+            pb.suspend(|| eprintln!("Note: {message}"));
+        }
+    }
+}
+
+// What to do with the headword atop a definition
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Headwords {
+    Keep,
+    Strip,
+}
+
+// What to do with usage examples in definitions
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Examples {
+    Keep,
+    Strip,
+}
+
+// Whether a definition goes on to its phrasal verbs and idioms (TFD lists them apart)
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Idioms {
+    Omit,
+    Append,
+}
+
+// How closely a cached entry's word must match the one looked up
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CacheMatch {
+    Exact,
+    // Ignoring accents, if there's no exact match
+    Loose,
+}
+
+// Whether to keep non-breaking spaces, which not every consumer of our output wants
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Spaces {
+    AsRendered,
+    Ascii,
+}
+
+// What to do when there's no entry for a word
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Misses {
+    // Print a list of similar words, if there is one, and call that a success
+    Suggest,
+    // Fail regardless, without a list
+    Fail,
+}
+
+// How to show an entry fetched to replace a cached one
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Updates {
+    Print,
+    // Just what changed, if anything
+    Diff,
+}
+
+// What to do with links within entries
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Links {
+    // Relative, as on the page (and dropped from plain text)
+    AsScraped,
+    // Pointing at the source site
+    Absolute,
+}
+
+// How much to say about what's going on behind the scenes
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Verbosity {
+    Normal,
+    Verbose,
+}
+
+// Whether to show a spinner while we wait, and if so, its frames and fetching message
+#[derive(Clone, PartialEq, Eq)]
+enum Progress {
+    Spinner { ticks: Vec<String>, message: String },
+    Hidden,
+}
+
+// Spinner presets, as on the command line or in the config
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SpinnerStyle {
+    Braille,
+    Ascii,
+    #[serde(rename = "none")]
+    Hidden,
+}
+
+// Ways of printing a result
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Plain,
+    Colored,
+    Json,
+    // Just the number of senses
+    Count,
+    // Just the pronunciation (the entry is still cached whole)
+    Pronunciation,
+    // Just the usage examples, like the sense count found in HTML
+    Examples,
+}
+
+impl Output {
+    // Color only plain text for a terminal, and only if the user hasn't opted out
+    fn choose(json_output: bool, no_color: bool, format: Format) -> Self {
+        if json_output {
+            Self::Json
+        } else if format != Format::Plain
+            || no_color
+            || env::var_os("NO_COLOR").is_some()
+            || !io::stdout().is_terminal()
+        {
+            Self::Plain
+        } else {
+            Self::Colored
+        }
+    }
+
+    // Text to print for an entry (JSON is handled separately)
+    // Any pronunciation goes on its own line, right below the headword
+    fn render(self, source: Source, body: &str, pronunciation: Option<&str>) -> String {
+        let body = match (pronunciation, body.split_once('\n')) {
+            (Some(pron), Some((headword, rest))) => {
+                format!("{headword}\nPronunciation: {pron}\n{rest}")
+            }
+            _ => body.to_owned(),
+        };
+
+        if self == Self::Colored {
+            colorize(source, &body)
+        } else {
+            body
+        }
+    }
+}
+
+// Function to look up a word's definition, then its etymology, each in its own cache
+// If only one is found, the other gets a note in its place
+fn look_up_both(session: &mut Session, desired_word: &str) -> Result<(), anyhow::Error> {
+    let mut found = false;
+
+    for mode in [Mode::Definition, Mode::Etymology] {
+        session.source = Source::default_for(mode);
+
+        // JSON Lines need no separator; each object says what it is
+        if mode == Mode::Etymology && session.output != Output::Json {
+            let separator = if session.format == Format::Html {
+                "<hr>"
+            } else {
+                "---"
+            };
+            outln!("\n{separator}\n")?;
+        }
+
+        match look_up(session, desired_word) {
+            Ok(()) => found = true,
+            Err(e) if e.is::<NotFound>() => {
+                if session.output == Output::Json {
+                    print_error_json(desired_word, session.source, &e)?;
+                } else {
+                    outln!("({e})")?;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if found {
+        Ok(())
+    } else {
+        Err(NotFound::Either.into())
+    }
+}
+
+// Function to look up a word in two places, each entry under a heading naming its source
+// Definitions come from two of TFD's dictionaries; etymologies, from two sites
+fn compare_sources(session: &mut Session, desired_word: &str) -> Result<(), anyhow::Error> {
+    check_word(desired_word, session.min_length)?;
+
+    let sources = match session.source.mode() {
+        Mode::Definition => [
+            (Source::Tfd, Dictionary::AmericanHeritage),
+            (Source::Tfd, Dictionary::Collins),
+        ],
+        Mode::Etymology => [
+            (Source::Etymonline, Dictionary::default()),
+            (Source::Wiktionary, Dictionary::default()),
+        ],
+    };
+
+    let mut found = false;
+
+    for (i, (source, dictionary)) in sources.into_iter().enumerate() {
+        session.dictionary = dictionary;
+
+        // JSON needs no headings, since each object has its source URL
+        if session.output != Output::Json {
+            let name = match source {
+                Source::Tfd => dictionary.name(),
+                _ => source.name(),
+            };
+
+            if i > 0 {
+                outln!()?;
+            }
+
+            if session.format == Format::Html {
+                outln!("<h1>{name}</h1>\n")?;
+            } else {
+                outln!("[{name}]\n")?;
+            }
+        }
+
+        // No fallback, so that a miss is a miss, rather than the other source again
+        match look_up_entry(session, source, desired_word, false) {
+            Ok(()) => found = true,
+            Err(e) if e.is::<NotFound>() => {
+                if session.output == Output::Json {
+                    print_error_json(desired_word, source, &e)?;
+                } else {
+                    outln!("({e})")?;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if found {
+        Ok(())
+    } else {
+        Err(not_found(session.source.mode()))
+    }
+}
+
+// How long a cached "not found" result holds (in seconds)
+const NOT_FOUND_TTL: i64 = 86_400;
+
+// Function to look up a single word, from cache or from the source site
+fn look_up(session: &Session, desired_word: &str) -> Result<(), anyhow::Error> {
+    check_word(desired_word, session.min_length)?;
+    look_up_entry(session, session.source, desired_word, true)
+}
+
+// Longer than any word or reasonable phrase (in characters)
+const MAX_WORD_LEN: usize = 100;
+
+// Function to catch input that can't be a word, before we go looking for it
+// Something that looks like a URL might still be one, so that only gets a warning
+fn check_word(word: &str, min_length: usize) -> Result<(), anyhow::Error> {
+    check_length(word, min_length)?;
+
+    if word.contains("://") || word.starts_with("www.") {
+        eprintln!("Warning: \"{word}\" looks like a URL, not a word");
+    }
+
+    Ok(())
+}
+
+// Function to catch input too short or too long to be a word, without any warnings
+// (A batch is checked up front like this, then word by word with check_word)
+fn check_length(word: &str, min_length: usize) -> Result<(), anyhow::Error> {
+    if word.trim().is_empty() {
+        return Err(anyhow!("Nothing to look up"));
+    }
+
+    if word.chars().count() < min_length {
+        return Err(anyhow!(
+            "\"{word}\" is shorter than {min_length} characters; not looking it up (see --min-length)"
+        ));
+    }
+
+    if word.chars().count() > MAX_WORD_LEN {
+        return Err(anyhow!(
+            "Input is too long to be a word or phrase (over {MAX_WORD_LEN} characters)"
+        ));
+    }
+
+    Ok(())
+}
+
+// With fallback, a word not found may be tried in another spelling or on another site,
+// then get suggestions; without it (as for those other tries), not found is simply not found
+#[allow(clippy::too_many_lines)]
+fn look_up_entry(
+    session: &Session,
+    source: Source,
+    desired_word: &str,
+    fallback: bool,
+) -> Result<(), anyhow::Error> {
+    let mode = source.mode();
+
+    // Only the usual site's (and dictionary's) entries are cached, so they don't get mixed up
+    let db_conn = session
+        .db_conn
+        .as_ref()
+        .filter(|_| source == session.source && session.dictionary == Dictionary::default());
+
+    // The regional spelling, if we're to try one and it differs
+    let respelled = session
+        .region
+        .filter(|_| fallback)
+        .and_then(|region| region.respell(desired_word));
+
+    // The word as typed is for display; the cache has its own, stricter form
+    let key = cache_key(desired_word);
+
+    // Did we get a cache hit?
+    let mut cache_hit = false;
+
+    // And if so, what did the cache say? (In case we're to compare it with a new version,
+    // or the site says there is none)
+    let mut previous = None;
+
+    //
+    // DB SETUP & CHECK FOR CACHED RESULTS
+    //
+
+    // Again, these operations can fail silently
+    if let Some(db_conn) = db_conn {
+        // Every lookup goes in the history, whatever comes of it
+        let _history = record_history(db_conn, desired_word, mode);
+
+        // Failing an exact match, an accent variant may do
+        let cached = query_db(db_conn, &key, mode, session.format)
+            .map(|entry| (key.clone(), entry))
+            .or_else(|e| match session.cache_match {
+                CacheMatch::Exact => Err(e),
+                CacheMatch::Loose => {
+                    loose_match(db_conn, &key, mode, session.format).map_or(Err(e), |word| {
+                        query_db(db_conn, &word, mode, session.format).map(|entry| (word, entry))
+                    })
+                }
+            });
+
+        // If we got a cache hit, handle it (usually print and return)
+        if let Ok((word, (entry, headword, created_at))) = cached {
+            let expired = session.max_age > 0 && unix_now() - created_at > session.max_age;
+
+            // Offline, even a stale entry is better than nothing
+            // A stale accent variant is another word's entry, though: it's left alone,
+            // and this word's own is fetched and cached apart
+            if (session.force_fetch || expired) && !session.offline {
+                if word == key {
+                    cache_hit = true;
+                    previous = Some((entry, headword));
+                }
+            } else {
+                print_entry(
+                    desired_word,
+                    source,
+                    &build_url(source, desired_word),
+                    true,
+                    &session.finish_entry(source, &entry),
+                    &session.finish_headword(headword),
+                    session.output,
+                )?;
+                return Ok(());
+            }
+        // Failing that, an entry in another format may have kept the HTML it came from
+        // Rendering that anew saves a trip to the site (and is cached in turn)
+        } else if let Some((html, headword, created_at)) = query_html(db_conn, &key, mode) {
+            let expired = session.max_age > 0 && unix_now() - created_at > session.max_age;
+
+            if !(session.force_fetch || expired) || session.offline {
+                let entry = session.render(source, &html)?;
+                let headword = Headword {
+                    pronunciation: headword
+                        .pronunciation
+                        .filter(|_| session.format != Format::Html),
+                    ..headword
+                };
+
+                if session.renderer == Renderer::Pandoc {
+                    let _update = update_cache(
+                        false,
+                        db_conn,
+                        &key,
+                        mode,
+                        session.format,
+                        &entry,
+                        &headword,
+                    )
+                    .and_then(|()| store_html(db_conn, &key, mode, session.format, &html));
+                }
+
+                print_entry(
+                    desired_word,
+                    source,
+                    &build_url(source, desired_word),
+                    true,
+                    &session.finish_entry(source, &entry),
+                    &session.finish_headword(headword),
+                    session.output,
+                )?;
+                return Ok(());
+            }
+        }
+
+        // If we recently failed to find this word, don't bother asking again
+        if session.cache_misses && !session.force_fetch {
+            if let Ok(missed_at) = query_not_found(db_conn, &key, mode) {
+                if unix_now() - missed_at < NOT_FOUND_TTL {
+                    return respelled.map_or_else(
+                        || Err(not_found(mode)),
+                        |respelled| look_up_entry(session, source, &respelled, false),
+                    );
+                }
+            }
+        }
+    }
+
+    // Moving on... unless we're not allowed to
+    if session.offline {
+        return Err(anyhow!("Not cached and --offline specified"));
+    }
+
+    // Build the relevant URL
+    let lookup_url = build_url(source, desired_word);
+
+    //
+    // SCRAPING & CACHING
+    //
+
+    // Start a progress spinner; this could take a second
+    let pb = spinner(&session.progress, None);
+
+    // Replacing a cached entry, ask for the page only if it has changed since
+    let validators = db_conn
+        .filter(|_| previous.is_some())
+        .and_then(|db_conn| query_validators(db_conn, &key, mode, session.format))
+        .unwrap_or_default();
+
+    // Make HTTP request and read response body into string
+    // A 404 is as good as an empty page; the fallback below will handle it
+    let fetched = fetch_page(session, &lookup_url, &validators, &pb);
+
+    // If it hasn't changed, neither has the entry: keep it, and restart its clock
+    if matches!(fetched, Err(GlossError::NotModified)) {
+        // (Only a cached copy's validators could have gotten this answer)
+        let (entry, headword) = previous.ok_or(GlossError::NotModified)?;
+        pb.finish_and_clear();
+
+        if let Some(db_conn) = db_conn {
+            let _refresh = refresh_cached(db_conn, &key, mode, session.format);
+        }
+
+        if session.output != Output::Json {
+            eprintln!("Unchanged since cached");
+        }
+
+        if session.updates == Updates::Diff {
+            out!("{}", entry_diff(&entry, &entry))?;
+            return Ok(());
+        }
+
+        print_entry(
+            desired_word,
+            source,
+            &lookup_url,
+            true,
+            &session.finish_entry(source, &entry),
+            &session.finish_headword(headword),
+            session.output,
+        )?;
+        return Ok(());
+    }
+
+    let (response_text, page_url, validators) = match fetched {
+        Err(GlossError::NotFound) => (String::new(), lookup_url.clone(), Validators::default()),
+        result => {
+            let page = result?;
+            (page.text, page.url.to_string(), page.validators)
+        }
+    };
+
+    // A redirect may have taken us somewhere unexpected (like a search page)
+    if page_url != lookup_url {
+        session.note(&pb, &format!("Redirected to {page_url}"));
+    }
+
+    // Take desired chunk of response text (in definition mode)
+    // In any case, parse what we have as an HTML tree
+    if !response_text.is_empty() && find_chunk(source, &response_text).is_none() {
+        session.note(&pb, "End of entry not found; parsing the whole page");
+    }
+    let parsed_chunk = take_chunk(source, &response_text);
+
+    // Take specific selectors that we want
+    let mut section_vec = get_section_vec(source, &parsed_chunk);
+
+    // Another of TFD's dictionaries may have been asked for; if it lacks the word, say so
+    // (Without fallback, as when comparing, it simply has no entry)
+    if source == Source::Tfd && session.dictionary != Dictionary::default() {
+        let sections = get_dictionary_sections(&parsed_chunk, session.dictionary);
+
+        if !sections.is_empty() || !fallback {
+            section_vec = sections;
+        } else if !section_vec.is_empty() && session.output != Output::Json {
+            pb.suspend(|| {
+                eprintln!(
+                    "Note: No {} entry; showing {} instead",
+                    session.dictionary.name(),
+                    Dictionary::default().name()
+                );
+            });
+        }
+    }
+
+    // If we got one or more sections...
+    if !section_vec.is_empty() {
+        let (results, headword) = session.compile_entry(source, &section_vec)?;
+
+        // Call out to Pandoc (unless we want HTML, or can do without)
+        let final_output = session.render(source, &results)?;
+
+        // Try to cache result, and the HTML it came from; this can fail silently
+        // Our own rendering isn't cached, so as not to displace Pandoc's
+        if let (Some(db_conn), Renderer::Pandoc) = (db_conn, session.renderer) {
+            let _update = update_cache(
+                cache_hit,
+                db_conn,
+                &key,
+                mode,
+                session.format,
+                &final_output,
+                &headword,
+            )
+            .and_then(|()| store_html(db_conn, &key, mode, session.format, &results))
+            .and_then(|()| store_validators(db_conn, &key, mode, session.format, &validators));
+
+            // A new entry may push an old one out
+            if !cache_hit && session.max_entries > 0 {
+                let _evict = evict_cache(db_conn, session.max_entries);
+            }
+        }
+
+        // We still need to print results, of course (after clearing the spinner)
+        pb.finish_and_clear();
+
+        if let (Updates::Diff, Some((previous, _))) = (session.updates, previous) {
+            out!("{}", entry_diff(&previous, &final_output))?;
+            return Ok(());
+        }
+
+        print_entry(
+            desired_word,
+            source,
+            &page_url,
+            false,
+            &session.finish_entry(source, &final_output),
+            &session.finish_headword(headword),
+            session.output,
+        )?;
+        return Ok(());
+    }
+
+    //
+    // FALLBACK
+    //
+
+    // Another spelling may have an entry where this one didn't
+    if let Some(respelled) = respelled {
+        pb.finish_and_clear();
+
+        match look_up_entry(session, source, &respelled, false) {
+            Err(e) if e.is::<NotFound>() => {}
+            result => return result,
+        }
+    }
+
+    // Likewise another site
+    if let Some(other) = session.fallback_source.filter(|_| fallback) {
+        pb.finish_and_clear();
+
+        if session.output != Output::Json {
+            eprintln!("{} has no entry; trying {}", source.name(), other.name());
+        }
+
+        match look_up_entry(session, other, desired_word, false) {
+            Err(e) if e.is::<NotFound>() => {}
+            result => return result,
+        }
+    }
+
+    // We can check for a list of similar words (if we can show it as-is, and may)
+    let suggestions = if fallback
+        && session.misses == Misses::Suggest
+        && matches!(session.output, Output::Plain | Output::Colored)
+    {
+        find_suggestions(session, desired_word, &parsed_chunk, &pb)
+    } else {
+        Vec::new()
+    };
+
+    // If we got something...
+    if !suggestions.is_empty() {
+        // Print an explanatory message, then the results (after clearing the spinner)
+        pb.finish_and_clear();
+        outln!("Did you mean:\n")?;
+        print_suggestion_list(&suggestions)?;
+        return Ok(());
+    }
+
+    // If still no dice... remember that, if asked to
+    if let (true, Some(db_conn)) = (session.cache_misses, db_conn) {
+        let _record = record_not_found(db_conn, &key, mode);
+    }
+
+    pb.finish_and_clear();
+    Err(not_found(mode))
+}
+
+// Read words from stdin with a prompt, looking each one up, until EOF or "quit"
+fn repl(session: &mut Session) -> Result<(), anyhow::Error> {
+    let mut line = String::new();
+
+    loop {
+        // The prompt shows which kind of entry we're after
+        let prompt = match session.source.mode() {
+            Mode::Definition => "def> ",
+            Mode::Etymology => "etym> ",
+        };
+        eprint!("{prompt}");
+
+        line.clear();
+        if io::stdin()
+            .read_line(&mut line)
+            .context("Failed to read from stdin")?
+            == 0
+        {
+            eprintln!();
+            return Ok(());
+        }
+
+        let word = normalize_word(&line);
+
+        match word.as_str() {
+            "" => continue,
+            "quit" => return Ok(()),
+            // Switch between definitions and etymologies
+            ":etym" => {
+                let mode = match session.source.mode() {
+                    Mode::Definition => Mode::Etymology,
+                    Mode::Etymology => Mode::Definition,
+                };
+                session.source = Source::default_for(mode);
+                continue;
+            }
+            _ => {}
+        }
+
+        if session.output == Output::Json {
+            if let Err(e) = look_up(session, &word) {
+                print_error_json(&word, session.source, &e)?;
+            }
+            continue;
+        }
+
+        if let Err(e) = look_up(session, &word) {
+            println!("{e}");
+        }
+
+        println!();
+    }
+}
+
+// A word that the source site doesn't have (and no suggestions, either)
+#[derive(Debug, Error)]
+enum NotFound {
+    #[error("Definition not found")]
+    Definition,
+    #[error("Etymology not found")]
+    Etymology,
+    #[error("No suggestions found")]
+    Suggestions,
+    #[error("Neither definition nor etymology found")]
+    Either,
+}
+
+// Error for a word that the source site doesn't have
+fn not_found(mode: Mode) -> anyhow::Error {
+    match mode {
+        Mode::Definition => NotFound::Definition.into(),
+        Mode::Etymology => NotFound::Etymology.into(),
+    }
+}
+
+// Function to gather suggested alternate words (empty if none)
+// Etymonline keeps these on its search page, which needs another request
+fn find_suggestions(
+    session: &Session,
+    desired_word: &str,
+    parsed_chunk: &Html,
+    pb: &ProgressBar,
+) -> Vec<String> {
+    let source = session.source;
+
+    let Some(search_url) = build_search_url(source, desired_word) else {
+        return get_suggestions(source, parsed_chunk);
+    };
+
+    // If the search fails, we just have no suggestions
+    pb.set_message("Searching...");
+    fetch_with_retries(
+        &session.client,
+        &search_url,
+        &Validators::default(),
+        session.retries,
+        pb,
+    )
+    .map(|search| get_suggestions(source, &take_chunk(source, &search.text)))
+    .unwrap_or_default()
+}
+
+// Function to print suggested words, one per line
+fn print_suggestion_list(suggestions: &[String]) -> io::Result<()> {
+    for suggestion in suggestions {
+        outln!("{suggestion}")?;
+    }
+
+    Ok(())
+}
+
+// Function to print only the suggestions for a word, even if it has an entry
+fn print_suggestions(session: &Session, desired_word: &str) -> Result<(), anyhow::Error> {
+    let source = session.source;
+    let pb = spinner(&session.progress, None);
+
+    // Etymonline's suggestions are on its search page, so we can skip the entry
+    let page = if build_search_url(source, desired_word).is_some() {
+        String::new()
+    } else {
+        match fetch_page(
+            session,
+            &build_url(source, desired_word),
+            &Validators::default(),
+            &pb,
+        ) {
+            Err(GlossError::NotFound) => String::new(),
+            result => result?.text,
+        }
+    };
+
+    let suggestions = find_suggestions(session, desired_word, &take_chunk(source, &page), &pb);
+    pb.finish_and_clear();
+
+    if suggestions.is_empty() {
+        return Err(NotFound::Suggestions.into());
+    }
+
+    print_suggestion_list(&suggestions)?;
+
+    Ok(())
+}
+
+// What we know of a headword apart from its entry: shown separately, or in --json
+// Other names and usage notes stay in the entry, too; JSON just has them apart
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Headword {
+    pronunciation: Option<String>,
+    inflections: Vec<String>,
+    also_called: Vec<String>,
+    usage_notes: Vec<String>,
+}
+
+// Structured form of a lookup result, for --json
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    word: &'a str,
+    mode: &'a str,
+    source_url: &'a str,
+    cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pronunciation: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    inflections: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    also_called: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    usage_notes: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl<'a> JsonEntry<'a> {
+    const fn new(desired_word: &'a str, source: Source, lookup_url: &'a str) -> Self {
+        Self {
+            word: desired_word,
+            mode: source.mode().name(),
+            source_url: lookup_url,
+            cached: false,
+            pronunciation: None,
+            inflections: &[],
+            also_called: &[],
+            usage_notes: &[],
+            body: None,
+            error: None,
+        }
+    }
+}
 
-    //
-    // "GLOBAL" VARIABLES
-    //
+// Function to print a result, either as-is or as JSON
+fn print_entry(
+    desired_word: &str,
+    source: Source,
+    lookup_url: &str,
+    cached: bool,
+    body: &str,
+    headword: &Headword,
+    output: Output,
+) -> Result<(), anyhow::Error> {
+    match output {
+        Output::Json => {
+            let entry = JsonEntry {
+                cached,
+                pronunciation: headword.pronunciation.as_deref(),
+                inflections: &headword.inflections,
+                also_called: &headword.also_called,
+                usage_notes: &headword.usage_notes,
+                body: Some(body),
+                ..JsonEntry::new(desired_word, source, lookup_url)
+            };
 
-    // Do we have flags?
-    let clear_cache = matches.get_flag("clear-cache");
-    let etym_mode = matches.get_flag("etymology");
-    let force_fetch = matches.get_flag("fetch-update");
+            let json = serde_json::to_string(&entry).context("Failed to serialize result")?;
+            outln!("{json}")?;
+        }
+        Output::Count => {
+            outln!("{}", count_senses(source, &Html::parse_fragment(body)))?;
+        }
+        Output::Pronunciation => {
+            let pronunciation = headword
+                .pronunciation
+                .as_deref()
+                .ok_or_else(|| anyhow!("No pronunciation found"))?;
+            outln!("{pronunciation}")?;
+        }
+        Output::Examples => {
+            let examples = get_examples(source, &Html::parse_fragment(body));
+            if examples.is_empty() {
+                return Err(anyhow!("No usage examples found for {desired_word}"));
+            }
 
-    // Take input and lowercase it
-    let desired_word = if clear_cache {
-        String::new() // Placeholder; we'll return soon, anyway
-    } else {
-        let input_word: &String = matches.get_one("INPUT").unwrap(); // Should be ok
-        input_word.to_lowercase()
+            for example in examples {
+                outln!("{example}")?;
+            }
+        }
+        Output::Colored | Output::Plain => {
+            out!(
+                "{}",
+                output.render(source, body, headword.pronunciation.as_deref())
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Function to print a failed lookup as JSON, for batch mode
+fn print_error_json(
+    desired_word: &str,
+    source: Source,
+    error: &anyhow::Error,
+) -> Result<(), anyhow::Error> {
+    let lookup_url = build_url(source, desired_word);
+
+    let entry = JsonEntry {
+        error: Some(error.to_string()),
+        ..JsonEntry::new(desired_word, source, &lookup_url)
     };
 
-    // What will be the path to the cache db? Is the db accessible?
-    let mut db_path = PathBuf::new();
-    let mut db_available = false;
+    let json = serde_json::to_string(&entry).context("Failed to serialize result")?;
+    outln!("{json}")?;
 
-    // Did we get a cache hit?
-    let mut cache_hit = false;
+    Ok(())
+}
 
-    //
-    // CACHE DIRECTORY
-    //
+// The longest we'll wait on a rate limit's Retry-After before giving up
+const MAX_RETRY_AFTER: Duration = Duration::from_mins(1);
 
-    // Most operations here can fail silently; caching is optional
-    if let Some(proj_dirs) = ProjectDirs::from("com", "theobeers", "gloss-word") {
-        let cache_dir = proj_dirs.cache_dir();
+// Function to make HTTP request, retrying transient failures with backoff
+// With validators from a cached copy, the request is conditional (see get_page)
+fn fetch_with_retries(
+    client: &Client,
+    lookup_url: &str,
+    validators: &Validators,
+    retries: u32,
+    pb: &ProgressBar,
+) -> Result<Page, GlossError> {
+    let mut attempt = 0;
 
-        // If we have clear-cache flag, handle it and return
-        if clear_cache {
-            if !cache_dir.exists() {
-                return Err(anyhow!("Cache directory not found"));
+    loop {
+        match get_page(client, lookup_url, validators) {
+            // If the site wants us to wait a long while, it's better to say so now
+            Err(e) if e.retry_after().is_some_and(|wait| wait > MAX_RETRY_AFTER) => {
+                return Err(e);
             }
-
-            trash::delete(cache_dir)?;
-            eprintln!("Cache directory deleted");
-            return Ok(());
+            Err(e) if attempt < retries && e.is_transient() => {
+                // Wait as long as we were asked to, or else 0.5s, 1s, 2s, etc.
+                thread::sleep(
+                    e.retry_after()
+                        .unwrap_or_else(|| Duration::from_millis(500 << attempt.min(6))),
+                );
+                attempt += 1;
+                pb.set_message(format!("Fetching... (retry {attempt} of {retries})"));
+            }
+            result => return result,
         }
+    }
+}
+
+// Function to guess whether the terminal can draw braille (and other non-ASCII text)
+// On Windows, only newer hosts can; elsewhere, it's up to the locale
+fn unicode_terminal() -> bool {
+    if cfg!(windows) {
+        return env::var_os("WT_SESSION").is_some() || env::var_os("TERM_PROGRAM").is_some();
+    }
+
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()))
+        .is_some_and(|locale| {
+            let locale = locale.to_lowercase();
+            locale.contains("utf-8") || locale.contains("utf8")
+        })
+}
 
-        // If we don't have the cache dir yet, try to create it
-        if !cache_dir.exists() {
-            let _dir = fs::create_dir_all(cache_dir);
+// Function to get a spinner's frames: custom ones (one per character), or a preset's
+// The last frame is shown when finished, so custom ones need at least two
+fn tick_strings(style: SpinnerStyle, custom: Option<&str>) -> Vec<String> {
+    if let Some(custom) = custom {
+        if custom.chars().count() >= 2 {
+            return custom.chars().map(String::from).collect();
         }
 
-        // Construct appropriate path for db
-        db_path.push(cache_dir);
-        db_path.push("entries.sqlite");
+        eprintln!("Warning: ignoring spinner-ticks; it needs at least two characters");
     }
 
-    //
-    // DB SETUP & CHECK FOR CACHED RESULTS
-    //
+    let preset: &[&str] = match style {
+        SpinnerStyle::Ascii => &["|", "/", "-", "\\", " "],
+        _ => &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
+    };
 
-    // Again, these operations can fail silently
-    if let Ok(db_conn) = Connection::open(&db_path) {
-        // Mark db available for later use
-        db_available = true;
-
-        // Create both tables, if they don't exist
-
-        let _create_dic = db_conn.execute(
-            "CREATE TABLE IF NOT EXISTS dictionary (
-                    word        TEXT UNIQUE NOT NULL,
-                    content     TEXT NOT NULL
-                )",
-            [],
-        );
+    preset.iter().map(|&tick| tick.to_owned()).collect()
+}
 
-        let _create_etym = db_conn.execute(
-            "CREATE TABLE IF NOT EXISTS etymology (
-                    word        TEXT UNIQUE NOT NULL,
-                    content     TEXT NOT NULL
-                )",
-            [],
-        );
+// Function to render a whole page, as fetched, the way a lookup would (but with no cache)
+// The entry is left for finish_entry and print_entry, like any other
+fn render_page(session: &Session, page: &str) -> Result<(String, Headword), anyhow::Error> {
+    let source = session.source;
+    let parsed_chunk = take_chunk(source, page);
+    let section_vec = if source == Source::Tfd && session.dictionary != Dictionary::default() {
+        get_dictionary_sections(&parsed_chunk, session.dictionary)
+    } else {
+        get_section_vec(source, &parsed_chunk)
+    };
 
-        // If we got a cache hit, handle it (usually print and return)
-        if let Ok(entry) = query_db(&db_conn, &desired_word, etym_mode) {
-            if force_fetch {
-                cache_hit = true;
-            } else {
-                print!("{entry}");
-                return Ok(());
-            }
-        }
+    if section_vec.is_empty() {
+        return Err(not_found(source.mode()));
     }
 
-    // Moving on...
+    let (results, headword) = session.compile_entry(source, &section_vec)?;
 
-    //
-    // SCRAPING & CACHING
-    //
+    Ok((session.render(source, &results)?, headword))
+}
+
+// Function to start a progress spinner with a message
+// Without one, it says whatever it says while fetching
+// A hidden one draws nothing at all, but can be handled just the same
+fn spinner(progress: &Progress, message: Option<&'static str>) -> ProgressBar {
+    let Progress::Spinner {
+        ticks,
+        message: fetching,
+    } = progress
+    else {
+        return ProgressBar::hidden();
+    };
+
+    let ticks: Vec<&str> = ticks.iter().map(String::as_str).collect();
 
-    // Start a progress spinner; this could take a second
     let pb = ProgressBar::new_spinner();
     pb.enable_steady_tick(Duration::from_millis(80));
     pb.set_style(
         ProgressStyle::default_spinner()
-            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
+            .tick_strings(&ticks)
             .template("{spinner} {msg}")
             .unwrap(),
     );
-    pb.set_message("Fetching...");
+    pb.set_message(message.map_or_else(|| fetching.clone(), str::to_owned));
 
-    // Build the relevant URL
-    let mut lookup_url: String;
+    // Replace any spinner that has since finished
+    *ACTIVE_SPINNER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = Some(pb.clone());
 
-    if etym_mode {
-        lookup_url = "https://www.etymonline.com/word/".to_owned();
-        lookup_url += &desired_word.replace(' ', "%20");
-    } else {
-        lookup_url = "https://www.thefreedictionary.com/".to_owned();
-        lookup_url += &desired_word.replace(' ', "+");
-    }
+    pb
+}
 
-    // Make HTTP request and read response body into string
-    let response_text = get_response_text(&lookup_url)?;
+// Function to get a page, using one fetched ahead of time if we have it
+fn fetch_page(
+    session: &Session,
+    url: &str,
+    validators: &Validators,
+    pb: &ProgressBar,
+) -> Result<Page, GlossError> {
+    let prefetched = session.prefetched.borrow_mut().remove(url);
+    prefetched.unwrap_or_else(|| {
+        fetch_with_retries(&session.client, url, validators, session.retries, pb)
+    })
+}
 
-    // Take desired chunk of response text (in definition mode)
-    // In any case, parse what we have as an HTML tree
-    let parsed_chunk = take_chunk(&response_text);
+// Function to fetch the pages for a batch of words (minus any already cached)
+fn prefetch(session: &Session, words: &[String], jobs: usize) {
+    let urls = prefetch_urls(session, words);
 
-    // Take specific selectors that we want
-    let section_vec = get_section_vec(etym_mode, &parsed_chunk);
+    let pb = spinner(&session.progress, None);
+    let pages = fetch_all(&session.client, &urls, session.retries, jobs);
+    pb.finish_and_clear();
 
-    // If we got one or more sections...
-    if !section_vec.is_empty() {
-        // Compile results into string
-        let results = compile_results(etym_mode, section_vec);
+    session.prefetched.borrow_mut().extend(pages);
+}
 
-        // Call out to Pandoc
-        let final_output = pandoc_primary(etym_mode, &results)?;
+// Function to list the pages a batch will need, leaving out any that look_up_entry
+// would do without: words cached (in this format or as HTML), or recently missed
+// Input that can't be a word is left out too; look_up rejects it without fetching
+fn prefetch_urls(session: &Session, words: &[String]) -> Vec<String> {
+    let source = session.source;
+    let mode = source.mode();
 
-        // Try to cache result; this can fail silently
-        if db_available {
-            let _update = update_cache(
-                cache_hit,
-                db_path,
-                &desired_word,
-                etym_mode,
-                &final_output,
-                force_fetch,
-            );
+    let answered = |word: &String| {
+        let Some(db_conn) = session.db_conn.as_ref().filter(|_| !session.force_fetch) else {
+            return false;
+        };
+
+        let key = cache_key(word);
+        let fresh = |created_at| session.max_age == 0 || unix_now() - created_at <= session.max_age;
+
+        query_db(db_conn, &key, mode, session.format)
+            .is_ok_and(|(_, _, created_at)| fresh(created_at))
+            || query_html(db_conn, &key, mode).is_some_and(|(_, _, created_at)| fresh(created_at))
+            || (session.cache_misses
+                && query_not_found(db_conn, &key, mode)
+                    .is_ok_and(|missed_at| unix_now() - missed_at < NOT_FOUND_TTL))
+    };
+
+    let mut urls: Vec<String> = words
+        .iter()
+        .filter(|word| check_length(word, session.min_length).is_ok() && !answered(word))
+        .map(|word| build_url(source, word))
+        .collect();
+    urls.sort();
+    urls.dedup();
+
+    urls
+}
+
+// Function to fetch many URLs at once, with up to `jobs` threads sharing one client
+// Only the network is involved; everything else happens in order, on the main thread
+fn fetch_all(
+    client: &Client,
+    urls: &[String],
+    retries: u32,
+    jobs: usize,
+) -> HashMap<String, Result<Page, GlossError>> {
+    let next = AtomicUsize::new(0);
+    let pages = Mutex::new(HashMap::new());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(urls.len()) {
+            scope.spawn(|| {
+                while let Some(url) = urls.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let page = fetch_with_retries(
+                        client,
+                        url,
+                        &Validators::default(),
+                        retries,
+                        &ProgressBar::hidden(),
+                    );
+                    pages
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .insert(url.clone(), page);
+                }
+            });
         }
+    });
 
-        // We still need to print results, of course (after clearing the spinner)
-        pb.finish_and_clear();
-        print!("{final_output}");
-        return Ok(());
+    pages.into_inner().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Words to study when the cache has none to offer, one per line
+const WORD_LIST: &str = include_str!("words.txt");
+
+// Function to compare a cached entry with a freshly fetched one, as a unified diff
+fn entry_diff(cached: &str, fetched: &str) -> String {
+    if cached == fetched {
+        return "No change\n".to_owned();
     }
 
-    //
-    // FALLBACK
-    //
+    TextDiff::from_lines(cached, fetched)
+        .unified_diff()
+        .header("cached", "fetched")
+        .to_string()
+}
 
-    // If we failed to get an etymology result, stop here
-    if etym_mode {
-        pb.finish_and_clear();
-        return Err(anyhow!("Etymology not found"));
+// Function to check the setup, printing a line for each part
+// Anything that fails would break lookups, so any failure is an error
+fn run_doctor(cache_dir: Option<&Path>, client: &Client) -> Result<(), anyhow::Error> {
+    let mut checks = vec![("Pandoc", check_pandoc())];
+
+    if let Some(cache_dir) = cache_dir {
+        checks.push(("Cache directory", check_cache_dir(cache_dir)));
+        checks.push((
+            "Cache database",
+            check_cache_db(&cache_dir.join("entries.sqlite")),
+        ));
+    } else {
+        checks.push(("Cache directory", Err(anyhow!("No home directory found"))));
     }
 
-    // In dictionary mode, we can check for a list of similar words
-    let suggestions_selector = Selector::parse("ul.suggestions li").unwrap();
-    let suggestions_vec: Vec<ElementRef> = parsed_chunk.select(&suggestions_selector).collect();
+    for source in [Source::Tfd, Source::Etymonline, Source::Wiktionary] {
+        let url = build_wotd_url(source);
+        let reached = get_response_text(client, url).map(|page| format!("reached {}", page.url));
+        checks.push((source.name(), reached.map_err(anyhow::Error::from)));
+    }
 
-    // If we got something...
-    if !suggestions_vec.is_empty() {
-        let mut results = String::new();
+    let mut failures = 0;
 
-        for element in &suggestions_vec {
-            results.push_str(&element.html());
+    for (name, result) in checks {
+        match result {
+            Ok(detail) => println!("✓ {name}: {detail}"),
+            Err(e) => {
+                failures += 1;
+                println!("✗ {name}: {e:#}");
+            }
         }
+    }
 
-        // Call out to Pandoc
-        let pandoc_output = pandoc_fallback(&results)?;
-
-        // Print an explanatory message, then the results (after clearing the spinner)
-        pb.finish_and_clear();
-        println!("Did you mean:\n");
-        print!("{pandoc_output}");
-        return Ok(());
+    if failures > 0 {
+        return Err(anyhow!("{failures} of the checks failed"));
     }
 
-    // If still no dice...
+    Ok(())
+}
+
+// Function to find Pandoc and its version
+#[cfg(feature = "pandoc")]
+fn check_pandoc() -> Result<String, anyhow::Error> {
+    let output = pandoc_command()
+        .arg("--version")
+        .output()
+        .map_err(|_| GlossError::PandocMissing)?;
+
+    let version = String::from_utf8_lossy(&output.stdout);
+    Ok(version
+        .lines()
+        .next()
+        .unwrap_or("version unknown")
+        .to_owned())
+}
+
+#[cfg(not(feature = "pandoc"))]
+#[allow(clippy::unnecessary_wraps)] // As the real one, which can fail
+fn check_pandoc() -> Result<String, anyhow::Error> {
+    Ok("not used (built without the \"pandoc\" feature)".to_owned())
+}
+
+// Function to make sure the cache directory exists and can be written to
+fn check_cache_dir(cache_dir: &Path) -> Result<String, anyhow::Error> {
+    fs::create_dir_all(cache_dir).context("Failed to create it")?;
+    let probe = cache_dir.join(".gloss-probe");
+    fs::write(&probe, "").context("Failed to write to it")?;
+    fs::remove_file(&probe).context("Failed to clean up after writing to it")?;
+    Ok(format!("{} is writable", cache_dir.display()))
+}
+
+// Function to pick a word at random, from the cache if it has any, or else from our list
+fn random_word(db_conn: Option<&Connection>, mode: Mode, format: Format) -> String {
+    let cached = db_conn.and_then(|db_conn| random_cached_word(db_conn, mode, format));
+
+    cached.unwrap_or_else(|| {
+        // The clock is random enough for this
+        let words: Vec<&str> = WORD_LIST.lines().filter(|word| !word.is_empty()).collect();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+
+        words[usize::try_from(nanos).unwrap_or_default() % words.len()].to_owned()
+    })
+}
+
+// Function to find out which word the source site is featuring today
+fn fetch_word_of_the_day(session: &Session) -> Result<String, anyhow::Error> {
+    let source = session.source;
+
+    let pb = spinner(&session.progress, Some("Finding word of the day..."));
+    let page = fetch_with_retries(
+        &session.client,
+        build_wotd_url(source),
+        &Validators::default(),
+        session.retries,
+        &pb,
+    );
     pb.finish_and_clear();
-    Err(anyhow!("Definition not found"))
+
+    get_word_of_the_day(source, &Html::parse_document(&page?.text))
+        .ok_or_else(|| anyhow!("Word of the day not found on {}", source.name()))
 }
 
-// Function to call Pandoc in case of suggested alternate words
-fn pandoc_fallback(results: &str) -> Result<String, anyhow::Error> {
-    // Write results string into a tempfile to pass to Pandoc
-    let mut pandoc_input = NamedTempFile::new().context("Failed to create tempfile")?;
-    write!(pandoc_input, "{results}").context("Failed to write to tempfile")?;
+// Function to find and print the audio pronunciations on a word's page
+fn print_audio_urls(session: &Session, desired_word: &str) -> Result<(), anyhow::Error> {
+    let source = session.source;
 
-    let pandoc = Command::new("pandoc")
-        .arg(pandoc_input.path())
-        .arg("-f")
-        .arg("html+smart-native_divs")
-        .arg("-t")
-        .arg("plain")
-        .output()
-        .context("Failed to execute Pandoc")?;
+    let pb = spinner(&session.progress, None);
+    let page = fetch_with_retries(
+        &session.client,
+        &build_url(source, desired_word),
+        &Validators::default(),
+        session.retries,
+        &pb,
+    );
+    pb.finish_and_clear();
+
+    let parsed_chunk = take_chunk(source, &page?.text);
+    let audio_urls = get_audio_urls(source, &get_section_vec(source, &parsed_chunk));
+
+    if audio_urls.is_empty() {
+        return Err(anyhow!("No audio pronunciation found"));
+    }
+
+    for url in audio_urls {
+        outln!("{url}")?;
+    }
 
-    let pandoc_output = str::from_utf8(&pandoc.stdout)
-        .context("Failed to convert Pandoc output to string")?
-        .to_owned();
+    Ok(())
+}
 
-    Ok(pandoc_output)
+// Current time as a unix timestamp, for cache bookkeeping
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX))
 }
 
-// Function to query db for cached results
-fn query_db(
-    db_conn: &Connection,
-    desired_word: &str,
-    etym_mode: bool,
-) -> Result<String, rusqlite::Error> {
-    let mut query = String::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::serve;
 
-    // Construct query as appropriate
-    if etym_mode {
-        query.push_str("SELECT * FROM etymology WHERE word = '");
-    } else {
-        query.push_str("SELECT * FROM dictionary WHERE word = '");
+    // A session with the defaults, quiet, using the given cache (if any)
+    pub fn test_session(db_conn: Option<Connection>) -> Session {
+        Session {
+            cache_match: CacheMatch::Exact,
+            cache_misses: false,
+            client: Client::new(),
+            db_conn,
+            dictionary: Dictionary::default(),
+            examples: Examples::Keep,
+            fallback_source: None,
+            force_fetch: false,
+            format: Format::Plain,
+            headwords: Headwords::Keep,
+            idioms: Idioms::Omit,
+            limit: None,
+            links: Links::AsScraped,
+            max_age: 0,
+            max_entries: 0,
+            min_length: 1,
+            misses: Misses::Suggest,
+            offline: false,
+            output: Output::Plain,
+            prefetched: RefCell::new(HashMap::new()),
+            spaces: Spaces::AsRendered,
+            updates: Updates::Print,
+            progress: Progress::Hidden,
+            region: None,
+            renderer: Renderer::Pandoc,
+            retries: 0,
+            sections: Sections::First,
+            senses: SenseFilter::default(),
+            source: Source::Tfd,
+            verbosity: Verbosity::Normal,
+            wrap: None,
+        }
     }
 
-    query.push_str(desired_word);
-    query.push('\'');
+    // A fresh cache, opened as for a lookup, which lasts as long as the TempDir
+    #[cfg(feature = "cache")]
+    fn test_cache() -> (tempfile::TempDir, Connection) {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let db_conn = open_cache(&cache_dir.path().join("entries.sqlite")).unwrap();
 
-    let mut stmt = db_conn.prepare(&query)?;
+        (cache_dir, db_conn)
+    }
 
-    // We're looking for only one row, and only its definition/etymology column
-    let entry_content: String = stmt.query_row([], |row| row.get(1))?;
+    #[cfg(feature = "cache")]
+    #[test]
+    fn cache_pronunciation() {
+        let (_cache_dir, db_conn) = test_cache();
 
-    Ok(entry_content)
-}
+        let body = "isth·mus\n\nn. pl. isth·mus·es or isth·mi (-mī′)\n";
+        let headword = Headword {
+            pronunciation: Some("(ĭs′məs)".to_owned()),
+            inflections: vec!["isthmuses".to_owned(), "isthmi".to_owned()],
+            ..Headword::default()
+        };
+        update_cache(
+            false,
+            &db_conn,
+            "isthmus",
+            Mode::Definition,
+            Format::Plain,
+            body,
+            &headword,
+        )
+        .unwrap();
 
-// Function to try to update cache with new results
-fn update_cache(
-    cache_hit: bool,
-    db_path: PathBuf,
-    desired_word: &str,
-    etym_mode: bool,
-    final_output: &str,
-    force_fetch: bool,
-) -> Result<(), rusqlite::Error> {
-    // Yes, this means a second db connection; I don't think it's so bad
-    let db_conn = Connection::open(db_path)?;
-
-    // If we have force-fetch flag and got a cache hit, update
-    if force_fetch && cache_hit {
-        if etym_mode {
-            db_conn.execute(
-                "UPDATE etymology SET content = (?1) WHERE word = (?2)",
-                [final_output, desired_word],
-            )?;
-        } else {
-            db_conn.execute(
-                "UPDATE dictionary SET content = (?1) WHERE word = (?2)",
-                [final_output, desired_word],
-            )?;
+        let (content, cached, _) =
+            query_db(&db_conn, "isthmus", Mode::Definition, Format::Plain).unwrap();
+        assert_eq!(cached, headword);
+        assert_eq!(
+            Output::Plain.render(Source::Tfd, &content, cached.pronunciation.as_deref()),
+            "isth·mus\nPronunciation: (ĭs′məs)\n\nn. pl. isth·mus·es or isth·mi (-mī′)\n"
+        );
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn prefetch_skips() {
+        let (_cache_dir, db_conn) = test_cache();
+
+        // One word cached as is, one only as HTML (from another format), one missed
+        for (word, format) in [("forest", Format::Plain), ("isthmus", Format::Html)] {
+            update_cache(
+                false,
+                &db_conn,
+                word,
+                Mode::Definition,
+                format,
+                "",
+                &Headword::default(),
+            )
+            .unwrap();
         }
-    // Else insert
-    } else if etym_mode {
-        db_conn.execute(
-            "INSERT INTO etymology (word, content) VALUES (?1, ?2)",
-            [desired_word, final_output],
-        )?;
-    } else {
-        db_conn.execute(
-            "INSERT INTO dictionary (word, content) VALUES (?1, ?2)",
-            [desired_word, final_output],
-        )?;
+        store_html(
+            &db_conn,
+            "isthmus",
+            Mode::Definition,
+            Format::Html,
+            "<h2>isthmus</h2>",
+        )
+        .unwrap();
+        record_not_found(&db_conn, "asdfgh", Mode::Definition).unwrap();
+
+        let words: Vec<String> = ["forest", "isthmus", "asdfgh", "atavism", "atavism"]
+            .map(str::to_owned)
+            .into();
+        let session = Session {
+            cache_misses: true,
+            ..test_session(Some(db_conn))
+        };
+
+        // Only the word we know nothing about needs fetching, once
+        assert_eq!(
+            prefetch_urls(&session, &words),
+            [build_url(Source::Tfd, "atavism")]
+        );
+
+        // Unless everything is to be fetched anew
+        let session = Session {
+            force_fetch: true,
+            ..session
+        };
+        assert_eq!(prefetch_urls(&session, &words).len(), 4);
     }
 
-    Ok(())
+    #[test]
+    fn cli_definition() {
+        build_cli().debug_assert();
+    }
+
+    #[test]
+    fn wrap_width() {
+        let wrap = |args: &[&str]| {
+            build_cli()
+                .try_get_matches_from(args)
+                .map(|matches| matches.get_one::<usize>("wrap").copied())
+        };
+
+        // A bare flag means the terminal's width
+        assert_eq!(wrap(&["gloss", "x", "--wrap"]).unwrap(), Some(0));
+        assert_eq!(wrap(&["gloss", "--wrap", "40", "x"]).unwrap(), Some(40));
+        assert_eq!(wrap(&["gloss", "x"]).unwrap(), None);
+
+        // Right after the flag, a word is taken for its width
+        assert!(wrap(&["gloss", "--wrap", "x"]).is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code(&not_found(Mode::Etymology)), EXIT_NOT_FOUND);
+        assert_eq!(
+            not_found(Mode::Etymology).to_string(),
+            "Etymology not found"
+        );
+        assert_eq!(exit_code(&NotFound::Suggestions.into()), EXIT_NOT_FOUND);
+        assert_eq!(exit_code(&NotFound::Either.into()), EXIT_NOT_FOUND);
+
+        let bad_gateway = anyhow::Error::new(GlossError::Status(reqwest::StatusCode::BAD_GATEWAY));
+        assert_eq!(exit_code(&bad_gateway), EXIT_NETWORK);
+
+        let pandoc = anyhow::Error::new(GlossError::PandocMissing).context("While converting");
+        assert_eq!(exit_code(&pandoc), EXIT_PANDOC);
+
+        assert_eq!(exit_code(&anyhow!("Cache directory not found")), EXIT_ERROR);
+    }
+
+    #[test]
+    fn input_validation() {
+        assert!(check_word("atavism", 1).is_ok());
+        assert!(check_word("coup d'état", 1).is_ok());
+        assert!(check_word("https://www.etymonline.com/word/forest", 1).is_ok());
+
+        assert!(check_word("", 1).is_err());
+        assert!(check_word(&normalize_word(" \t "), 1).is_err());
+        assert!(check_word(&"a".repeat(MAX_WORD_LEN), 1).is_ok());
+        assert!(check_word(&"a".repeat(MAX_WORD_LEN + 1), 1).is_err());
+
+        assert!(check_word("a", 2).is_err());
+        assert!(check_word("ox", 2).is_ok());
+        assert!(check_word("\u{e9}t\u{e9}", 3).is_ok());
+    }
+
+    #[test]
+    fn clipboard_selection() {
+        assert_eq!(clipboard_word("  Atavism\n").as_deref(), Some("atavism"));
+        assert_eq!(clipboard_word("ice  cream").as_deref(), Some("ice cream"));
+        assert_eq!(
+            clipboard_word("coup d'état").as_deref(),
+            Some("coup d'état")
+        );
+        assert_eq!(
+            clipboard_word("\"Atavism,\" she said, was the word.").as_deref(),
+            Some("atavism")
+        );
+        assert_eq!(clipboard_word(" \n\t"), None);
+        assert_eq!(clipboard_word("... and so on"), None);
+    }
+
+    #[test]
+    fn prefetch_checks() {
+        let words: Vec<String> = ["", "atavism", &"a".repeat(MAX_WORD_LEN + 1)]
+            .map(str::to_owned)
+            .into();
+
+        // Only real candidates are fetched ahead of time
+        assert_eq!(
+            prefetch_urls(&test_session(None), &words),
+            [build_url(Source::Tfd, "atavism")]
+        );
+
+        // Including the minimum length
+        let session = Session {
+            min_length: 8,
+            ..test_session(None)
+        };
+        assert!(prefetch_urls(&session, &words).is_empty());
+    }
+
+    #[test]
+    fn parallel_fetch() {
+        // Answer each request with the path that was asked for
+        let (base, _) = serve(|request| {
+            let path = request.split(' ').nth(1).unwrap_or_default();
+            format!("200 OK\r\nContent-Length: {}\r\n\r\n{path}", path.len())
+        });
+
+        let client = build_client(
+            Duration::from_secs(5),
+            Duration::from_secs(5),
+            USER_AGENT,
+            None,
+        )
+        .unwrap();
+        let urls: Vec<String> = (0..10).map(|i| format!("{base}/word{i}")).collect();
+
+        let pages = fetch_all(&client, &urls, 0, 4);
+        assert_eq!(pages.len(), 10);
+        for (i, url) in urls.iter().enumerate() {
+            assert_eq!(pages[url].as_ref().unwrap().text, format!("/word{i}"));
+        }
+    }
+
+    #[test]
+    fn config_file() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let path = config_dir.path().join("config.toml");
+
+        // No file, no problem
+        assert!(load_config(&path).mode.is_none());
+
+        fs::write(
+            &path,
+            "mode = \"etymology\"\nformat = \"markdown\"\nmax-age = 7\n",
+        )
+        .unwrap();
+        let config = load_config(&path);
+        assert_eq!(config.mode, Some(Mode::Etymology));
+        assert_eq!(config.format, Some(Format::Markdown));
+        assert_eq!(config.max_age, Some(7));
+
+        // Flags outrank the file, which outranks defaults
+        let matches = build_cli().get_matches_from(["gloss", "--timeout", "3", "word"]);
+        assert_eq!(or_config(&matches, "timeout", Some(30_u64)), 3);
+        assert_eq!(or_config(&matches, "max-age", config.max_age), 7);
+        assert_eq!(or_config(&matches, "retries", None::<u32>), 2);
+        assert_eq!(or_config(&matches, "connect-timeout", None::<u64>), 5);
+
+        // A bad file is ignored, not fatal
+        fs::write(&path, "mode = \"dictionary\"\n").unwrap();
+        assert!(load_config(&path).mode.is_none());
+    }
+
+    #[test]
+    fn ipa_only() {
+        let body = "fo·rest\n\nn.\n\n1.  A dense growth of trees.\n";
+        let url = build_url(Source::Tfd, "forest");
+
+        // Without a pronunciation there's nothing to print
+        let missing = print_entry(
+            "forest",
+            Source::Tfd,
+            &url,
+            true,
+            body,
+            &Headword::default(),
+            Output::Pronunciation,
+        );
+        assert_eq!(missing.unwrap_err().to_string(), "No pronunciation found");
+
+        let headword = Headword {
+            pronunciation: Some("(fôr′ĭst)".to_owned()),
+            ..Headword::default()
+        };
+        assert!(print_entry(
+            "forest",
+            Source::Tfd,
+            &url,
+            true,
+            body,
+            &headword,
+            Output::Pronunciation
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn examples_only() {
+        let url = build_url(Source::Tfd, "run");
+
+        let missing = print_entry(
+            "flee",
+            Source::Tfd,
+            &url,
+            true,
+            r#"<h2>flee</h2><div class="pseg"><i>v.</i><div class="ds-single">To run away.</div></div>"#,
+            &Headword::default(),
+            Output::Examples,
+        );
+        assert_eq!(
+            missing.unwrap_err().to_string(),
+            "No usage examples found for flee"
+        );
+
+        assert!(print_entry(
+            "run",
+            Source::Tfd,
+            &url,
+            true,
+            r#"<h2>run</h2><div class="pseg"><i>v.</i><div class="ds-list"><b>1. </b>To move swiftly on foot: <span class="illustration">ran to catch the bus</span>.</div></div>"#,
+            &Headword::default(),
+            Output::Examples
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn no_color_env() {
+        // Whatever the environment had is put back afterward
+        let previous = env::var_os("NO_COLOR");
+        env::set_var("NO_COLOR", "1");
+
+        let body = "at·a·vism\n\nn.\n\n1.  The reappearance of a characteristic.\n";
+        let output = Output::choose(false, false, Format::Plain);
+
+        assert!(output == Output::Plain);
+        assert_eq!(output.render(Source::Tfd, body, None), body);
+        assert!(!output
+            .render(Source::Tfd, body, Some("(ăt′ə-vĭz′əm)"))
+            .contains('\x1b'));
+
+        // The flag wins regardless, and JSON is never colored
+        assert!(Output::choose(false, true, Format::Plain) == Output::Plain);
+        assert!(Output::choose(true, false, Format::Plain) == Output::Json);
+
+        match previous {
+            Some(value) => env::set_var("NO_COLOR", value),
+            None => env::remove_var("NO_COLOR"),
+        }
+    }
+
+    #[test]
+    fn color_formats() {
+        // Only plain text is ever colored
+        assert!(Output::choose(false, false, Format::Markdown) == Output::Plain);
+        assert!(Output::choose(false, false, Format::Html) == Output::Plain);
+    }
+
+    #[test]
+    fn spinner_ticks() {
+        assert_eq!(tick_strings(SpinnerStyle::Ascii, None)[..2], ["|", "/"]);
+        assert_eq!(tick_strings(SpinnerStyle::Braille, None).len(), 10);
+        assert_eq!(
+            tick_strings(SpinnerStyle::Braille, Some(".oO")),
+            [".", "o", "O"]
+        );
+        assert_eq!(tick_strings(SpinnerStyle::Ascii, Some("*")).len(), 5);
+
+        let config: Config = toml::from_str("spinner = \"none\"").unwrap();
+        assert_eq!(config.spinner, Some(SpinnerStyle::Hidden));
+    }
+
+    #[test]
+    fn page_rendering() {
+        let page = r#"<div id="Definition"><section data-src="hm">
+            <h2>forest</h2><div class="pseg"><div class="ds-list">Woods, or a dense growth of trees</div></div>
+        </section></div><div>Thesaurus</div>"#;
+
+        let html_session = Session {
+            format: Format::Html,
+            ..test_session(None)
+        };
+        let (html, _) = render_page(&html_session, page).unwrap();
+        assert!(html.contains("<h2>forest</h2>"));
+        assert!(html.ends_with('\n'));
+
+        // What's rendered is finished like any lookup's entry
+        #[cfg(feature = "render")]
+        {
+            let session = Session {
+                renderer: Renderer::Builtin,
+                wrap: Some(12),
+                ..test_session(None)
+            };
+            let (plain, _) = render_page(&session, page).unwrap();
+            assert!(plain.contains("Woods, or a dense growth of trees"));
+            assert!(session
+                .finish_entry(Source::Tfd, &plain)
+                .lines()
+                .all(|line| line.chars().count() <= 12));
+        }
+
+        let etym_session = Session {
+            source: Source::Etymonline,
+            ..html_session
+        };
+        let empty = render_page(&etym_session, page);
+        assert!(empty.unwrap_err().is::<NotFound>());
+    }
+
+    #[test]
+    fn update_diff() {
+        let cached = "forest (n.)\n\nlate 13c.\n";
+        assert_eq!(entry_diff(cached, cached), "No change\n");
+
+        let fetched = "forest (n.)\n\nc. 1300\n";
+        assert_eq!(
+            entry_diff(cached, fetched),
+            "--- cached\n+++ fetched\n@@ -1,3 +1,3 @@\n forest (n.)\n \n-late 13c.\n+c. 1300\n"
+        );
+    }
+
+    #[test]
+    fn output_file() {
+        let out_dir = tempfile::tempdir().unwrap();
+        let dir = out_dir.path().join("notes");
+
+        let path = word_file(&dir, "and/or", Output::Plain, Format::Markdown);
+        assert_eq!(path, dir.join("and_or.md"));
+        assert_eq!(
+            word_file(&dir, "atavism", Output::Json, Format::Plain),
+            dir.join("atavism.json")
+        );
+
+        // Parent directories are created as needed
+        open_output(&path).unwrap();
+        outln!("and/or, conj.").unwrap();
+        *OUTPUT.lock().unwrap() = Sink::Stdout;
+        assert_eq!(fs::read_to_string(&path).unwrap(), "and/or, conj.\n");
+
+        // A path that can't be written is an error, not a panic
+        assert!(open_output(out_dir.path()).is_err());
+    }
+
+    #[test]
+    fn pager_choice() {
+        assert_eq!(pager_command(None, None).as_deref(), Some("less -R"));
+        assert_eq!(
+            pager_command(None, Some("more".to_owned())).as_deref(),
+            Some("more")
+        );
+        assert_eq!(
+            pager_command(Some("most"), Some("more".to_owned())).as_deref(),
+            Some("most")
+        );
+
+        assert!(pager_command(None, Some(String::new())).is_none());
+        assert!(pager_command(Some("cat"), None).is_none());
+    }
+
+    #[test]
+    fn doctor_checks() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let nested = cache_dir.path().join("gloss-word");
+        assert!(check_cache_dir(&nested).is_ok());
+    }
 }
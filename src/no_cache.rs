@@ -0,0 +1,160 @@
+// Stand-ins for the SQLite cache, when built without the "cache" feature
+// There's never a connection, so every lookup goes to the network; cache commands fail
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use gloss_word::{Format, Mode, Validators};
+
+use crate::Headword;
+
+// What the cache commands say instead
+const NO_CACHE: &str = "This build has no cache (the \"cache\" feature was disabled)";
+
+// Can't be created, so nothing that takes one is ever called
+pub enum Connection {}
+
+#[allow(clippy::unnecessary_wraps)] // As the real one, which can fail
+pub fn check_cache_db(_db_path: &Path) -> Result<String, anyhow::Error> {
+    Ok("none (built without the \"cache\" feature)".to_owned())
+}
+
+pub fn print_cache_stats(_db_path: &Path) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn export_cache(_db_path: &Path, _export_path: &Path, _csv: bool) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn import_cache(_db_path: &Path, _import_path: &Path) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub const fn open_cache(_db_path: &Path) -> Option<Connection> {
+    None
+}
+
+pub fn query_db(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+    _format: Format,
+) -> Result<(String, Headword, i64), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub const fn query_html(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+) -> Option<(String, Headword, i64)> {
+    None
+}
+
+pub fn store_html(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+    _format: Format,
+    _html: &str,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub const fn query_validators(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+    _format: Format,
+) -> Option<Validators> {
+    None
+}
+
+pub fn store_validators(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+    _format: Format,
+    _validators: &Validators,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn refresh_cached(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+    _format: Format,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub const fn loose_match(
+    _db_conn: &Connection,
+    _key: &str,
+    _mode: Mode,
+    _format: Format,
+) -> Option<String> {
+    None
+}
+
+pub fn update_cache(
+    _cache_hit: bool,
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+    _format: Format,
+    _final_output: &str,
+    _headword: &Headword,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn evict_cache(_db_conn: &Connection, _max_entries: usize) -> Result<usize, anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn query_not_found(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+) -> Result<i64, anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn record_not_found(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn vacuum_cache(_db_path: &Path) -> Result<(u64, u64), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn forget_cached(_db_path: &Path, _word: &str, _mode: Mode) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub const fn random_cached_word(
+    _db_conn: &Connection,
+    _mode: Mode,
+    _format: Format,
+) -> Option<String> {
+    None
+}
+
+pub fn record_history(
+    _db_conn: &Connection,
+    _desired_word: &str,
+    _mode: Mode,
+) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
+
+pub fn print_history(_db_path: &Path, _count: usize) -> Result<(), anyhow::Error> {
+    Err(anyhow!(NO_CACHE))
+}
@@ -0,0 +1,323 @@
+// Pandoc, for turning scraped HTML into polished text
+// Built only with the "pandoc" feature (on by default); otherwise we make do with render_plain
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::str; // For str::from_utf8
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use regex::Regex;
+use tempfile::{NamedTempFile, TempDir};
+
+use crate::{decode_entities, Format, GlossError, Source};
+
+// Pandoc input files go in here, so they can be removed even if we're interrupted
+static SCRATCH_DIR: Mutex<Option<TempDir>> = Mutex::new(None);
+
+// Whether to replace invalid UTF-8 in Pandoc output, rather than fail (see --lossy)
+static LOSSY_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+// A particular Pandoc to run, if not the one on the PATH (see --pandoc-path)
+static PANDOC_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// What we run when no path is given, found on the PATH
+const PANDOC: &str = "pandoc";
+
+#[must_use]
+// A command to run Pandoc: the one set with set_pandoc_path, or else the usual one
+pub fn pandoc_command() -> Command {
+    let path = PANDOC_PATH.lock().unwrap_or_else(PoisonError::into_inner);
+
+    path.as_deref()
+        .map_or_else(|| Command::new(PANDOC), Command::new)
+}
+
+// Run the Pandoc at this path from now on, rather than the one on the PATH
+// It's checked now, so that a typo doesn't pass for Pandoc not being installed
+pub fn set_pandoc_path(path: &Path) -> Result<(), GlossError> {
+    check_executable(path)?;
+
+    *PANDOC_PATH.lock().unwrap_or_else(PoisonError::into_inner) = Some(path.to_owned());
+    Ok(())
+}
+
+// Make sure there's a file at this path that we could run
+fn check_executable(path: &Path) -> Result<(), GlossError> {
+    let bad_path = |reason| GlossError::PandocPath {
+        path: path.to_owned(),
+        reason,
+    };
+
+    let metadata = fs::metadata(path).map_err(|_| bad_path("does not exist"))?;
+    if !metadata.is_file() {
+        return Err(bad_path("is not a file"));
+    }
+
+    // Elsewhere, there's no permission bit to check; we'll find out when we run it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(bad_path("is not executable"));
+        }
+    }
+
+    Ok(())
+}
+
+// Write input to a tempfile and run Pandoc on it with the given arguments
+// All Pandoc calls go through here, so that failures are reported consistently
+pub fn run_pandoc(input: &str, args: &[&str]) -> Result<String, GlossError> {
+    let mut input_file =
+        scratch_file().map_err(|e| GlossError::io(e, "Failed to create tempfile"))?;
+    write!(input_file, "{input}").map_err(|e| GlossError::io(e, "Failed to write to tempfile"))?;
+
+    let pandoc = pandoc_command()
+        .arg(input_file.path())
+        .args(args)
+        .output()
+        .map_err(|e| pandoc_spawn_error(&e))?;
+
+    pandoc_output(&pandoc)
+}
+
+// Take Pandoc's stdout, unless it failed, in which case pass along what it said
+fn pandoc_output(pandoc: &Output) -> Result<String, GlossError> {
+    if !pandoc.status.success() {
+        let stderr = String::from_utf8_lossy(&pandoc.stderr).trim().to_owned();
+
+        return Err(GlossError::PandocFailed(if stderr.is_empty() {
+            pandoc.status.to_string()
+        } else {
+            stderr
+        }));
+    }
+
+    decode_output(&pandoc.stdout, LOSSY_OUTPUT.load(Ordering::Relaxed))
+}
+
+// Decode Pandoc's stdout as UTF-8: strictly, or with replacement characters if lossy
+fn decode_output(stdout: &[u8], lossy: bool) -> Result<String, GlossError> {
+    if lossy {
+        return Ok(String::from_utf8_lossy(stdout).into_owned());
+    }
+
+    let output = str::from_utf8(stdout)?.to_owned();
+
+    Ok(output)
+}
+
+// Have any invalid UTF-8 in Pandoc output replaced, instead of failing the lookup
+// This applies to every later Pandoc call in the process
+pub fn set_lossy_output(lossy: bool) {
+    LOSSY_OUTPUT.store(lossy, Ordering::Relaxed);
+}
+
+// Create a tempfile in the scratch directory, making the directory if need be
+fn scratch_file() -> Result<NamedTempFile, io::Error> {
+    let mut scratch_dir = SCRATCH_DIR.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let dir = match scratch_dir.take() {
+        Some(dir) => dir,
+        None => tempfile::Builder::new().prefix("gloss-word-").tempdir()?,
+    };
+
+    let file = NamedTempFile::new_in(dir.path());
+    *scratch_dir = Some(dir);
+    file
+}
+
+// Delete the scratch directory and anything left in it
+// Call this before exiting, especially from a signal handler
+pub fn remove_scratch_dir() {
+    let scratch_dir = SCRATCH_DIR
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+
+    if let Some(dir) = scratch_dir {
+        let _removed = dir.close();
+    }
+}
+
+// A missing binary gets its own error, with a hint on how to fix it
+fn pandoc_spawn_error(error: &io::Error) -> GlossError {
+    if error.kind() == io::ErrorKind::NotFound {
+        GlossError::PandocMissing
+    } else {
+        GlossError::PandocFailed(error.to_string())
+    }
+}
+
+// Function to convert to plain text with Pandoc, as a final step
+// This used to be duplicated in pandoc_primary, but jscpd was complaining
+pub fn pandoc_plain(input: &str) -> Result<String, GlossError> {
+    run_pandoc(input, &["-t", "plain"]).map(|output| decode_entities(&output))
+}
+
+// How Pandoc should read scraped HTML: with smart punctuation, and without divs
+const HTML_READER: &str = "html+smart-native_divs";
+
+// Convert any HTML fragment to plain text with Pandoc, and nothing more
+// For entries, pandoc_primary does the same by way of Markdown, with cleanup between
+pub fn render_html_to_text(html: &str) -> Result<String, GlossError> {
+    run_pandoc(html, &["-f", HTML_READER, "-t", "plain"]).map(|output| decode_entities(&output))
+}
+
+// Main Pandoc function
+pub fn pandoc_primary(source: Source, format: Format, results: &str) -> Result<String, GlossError> {
+    // HTML needs no conversion at all
+    if format == Format::Html {
+        return Ok(results.to_owned() + "\n");
+    }
+
+    // Convert results to Markdown first, so we can clean them up
+    let output_1 = &run_pandoc(
+        results,
+        &["-f", HTML_READER, "-t", "markdown", "--wrap=none"],
+    )?;
+
+    // Make regex (and simple text) replacements, depending on source site
+    let cleaned = match source {
+        Source::Tfd => {
+            // Un-bold numbered list labels
+            let re_list_1 = Regex::new(r"\n\*\*(?P<a>\d+\.)\*\*").unwrap();
+            let after_1 = re_list_1.replace_all(output_1, "\n$a");
+
+            // Un-bold and indent lettered list labels
+            let re_list_2 = Regex::new(r"\n\*\*(?P<b>[a-z]\.)\*\*").unwrap();
+            let after_2 = re_list_2.replace_all(&after_1, "\n    $b");
+
+            // Un-escape double quotes
+            after_2.replace(r#"\\""#, r#"""#)
+        }
+        Source::Etymonline | Source::Wiktionary => {
+            // Remove any figures
+            let re_figures = Regex::new(r"(?m)\n\n!\[.+$").unwrap();
+            let after_1 = re_figures.replace_all(output_1, "");
+
+            // Un-escape double quotes
+            // I don't know why Pandoc is outputting these to begin with
+            after_1.replace(r#"\\""#, r#"""#)
+        }
+    };
+
+    // For Markdown we're done; plain text takes one more step
+    match format {
+        Format::Markdown => Ok(cleaned),
+        Format::Plain | Format::Html => pandoc_plain(&cleaned),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scratch_cleanup() {
+        let file = scratch_file().unwrap();
+        let dir = file.path().parent().unwrap().to_owned();
+        assert!(dir.exists());
+
+        // Removal takes any lingering files with it
+        let (_, path) = file.keep().unwrap();
+        remove_scratch_dir();
+        assert!(!path.exists());
+        assert!(!dir.exists());
+
+        // And we can start over afterward
+        assert!(scratch_file().is_ok());
+        remove_scratch_dir();
+    }
+
+    #[test]
+    fn pandoc_missing() {
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        assert!(matches!(
+            pandoc_spawn_error(&missing),
+            GlossError::PandocMissing
+        ));
+
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            pandoc_spawn_error(&denied),
+            GlossError::PandocFailed(_)
+        ));
+    }
+
+    #[test]
+    fn pandoc_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("pandoc");
+
+        assert_eq!(
+            set_pandoc_path(&missing).unwrap_err().to_string(),
+            format!("Pandoc path {} does not exist", missing.display())
+        );
+        assert_eq!(
+            set_pandoc_path(dir.path()).unwrap_err().to_string(),
+            format!("Pandoc path {} is not a file", dir.path().display())
+        );
+
+        // A file we can run will do (this checks without switching to it)
+        fs::write(&missing, "").unwrap();
+        if cfg!(unix) {
+            assert!(matches!(
+                check_executable(&missing),
+                Err(GlossError::PandocPath { .. })
+            ));
+        }
+        assert!(check_executable(&std::env::current_exe().unwrap()).is_ok());
+
+        // Nothing has been set, so the PATH's Pandoc is still the one run
+        assert_eq!(pandoc_command().get_program(), PANDOC);
+    }
+
+    #[test]
+    #[ignore = "needs Pandoc installed"]
+    fn html_to_text() {
+        assert_eq!(
+            render_html_to_text("<div><p>A narrow strip of land.</p></div>").unwrap(),
+            "A narrow strip of land.\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pandoc_exit_status() {
+        let run = |script: &str| Command::new("sh").args(["-c", script]).output().unwrap();
+
+        assert_eq!(pandoc_output(&run("printf ok")).unwrap(), "ok");
+        assert_eq!(
+            pandoc_output(&run(
+                "printf partial; echo 'Unknown reader: htm' >&2; exit 2"
+            ))
+            .unwrap_err()
+            .to_string(),
+            "Pandoc failed: Unknown reader: htm"
+        );
+        assert_eq!(
+            pandoc_output(&run("exit 1")).unwrap_err().to_string(),
+            "Pandoc failed: exit status: 1"
+        );
+    }
+
+    #[test]
+    fn lossy_output() {
+        let bytes = b"isthmus \xff narrow";
+
+        assert!(matches!(
+            decode_output(bytes, false),
+            Err(GlossError::PandocOutput(_))
+        ));
+        assert_eq!(
+            decode_output(bytes, true).unwrap(),
+            "isthmus \u{fffd} narrow"
+        );
+        assert_eq!(decode_output(b"forest", false).unwrap(), "forest");
+    }
+}
@@ -0,0 +1,99 @@
+// Our own conversion of compiled results to plain text, for when Pandoc isn't wanted
+// Built only with the "render" feature (on by default), for --no-pandoc or a build without Pandoc
+
+use regex::Regex;
+use scraper::{ElementRef, Html};
+
+#[must_use]
+// Convert compiled results to plain text without Pandoc
+// The layout imitates Pandoc's, closely enough for colorize to work on it
+pub fn render_plain(results: &str) -> String {
+    let fragment = Html::parse_fragment(results);
+
+    let mut paragraphs = Vec::new();
+    let mut current = String::new();
+    collect_paragraphs(fragment.root_element(), &mut paragraphs, &mut current);
+    end_paragraph(&mut paragraphs, &mut current);
+
+    // Numbered senses get Pandoc's double space; lettered ones are indented too
+    let re_number = Regex::new(r"^(?P<label>\d+\.) ").unwrap();
+    let re_letter = Regex::new(r"^(?P<label>[a-z]\.) ").unwrap();
+
+    let mut output = paragraphs
+        .iter()
+        .map(|paragraph| {
+            let after_1 = re_number.replace(paragraph, "$label  ");
+            re_letter.replace(&after_1, "    $label  ").into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    output.push('\n');
+    output
+}
+
+// Walk the tree, starting a new paragraph at each block element
+fn collect_paragraphs(element: ElementRef, paragraphs: &mut Vec<String>, current: &mut String) {
+    for child in element.children() {
+        if let Some(text) = child.value().as_text() {
+            current.push_str(text);
+            continue;
+        }
+
+        let Some(child) = ElementRef::wrap(child) else {
+            continue;
+        };
+
+        match child.value().name() {
+            "figure" | "img" | "script" | "style" => {}
+            "br" => end_paragraph(paragraphs, current),
+            "hr" => {
+                end_paragraph(paragraphs, current);
+                paragraphs.push("-".repeat(72));
+            }
+            "blockquote" | "div" | "h1" | "h2" | "h3" | "h4" | "li" | "ol" | "p" | "section"
+            | "ul" => {
+                end_paragraph(paragraphs, current);
+                collect_paragraphs(child, paragraphs, current);
+                end_paragraph(paragraphs, current);
+            }
+            _ => collect_paragraphs(child, paragraphs, current),
+        }
+    }
+}
+
+// Collapse whitespace in the paragraph so far and set it aside, if not empty
+fn end_paragraph(paragraphs: &mut Vec<String>, current: &mut String) {
+    let paragraph = current.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !paragraph.is_empty() {
+        paragraphs.push(paragraph);
+    }
+    current.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use owo_colors::OwoColorize;
+
+    use crate::{colorize, Source};
+
+    #[test]
+    fn builtin_renderer() {
+        let results = r#"<h2>at·a·vism</h2><div class="pseg"><i>n.</i><div class="ds-list"><b>1. </b>The  reappearance
+            of a characteristic.<div class="sds-list"><b>a. </b>A throwback.</div></div></div><hr class="hmsep"><h2>at·a·vist</h2>"#;
+
+        let output = render_plain(results);
+        assert_eq!(
+            output,
+            format!(
+                "at·a·vism\n\nn.\n\n1.  The reappearance of a characteristic.\n\n    a.  A throwback.\n\n{}\n\nat·a·vist\n",
+                "-".repeat(72)
+            )
+        );
+
+        // Close enough to Pandoc's layout to color the same way
+        assert!(colorize(Source::Tfd, &output).contains(&format!("{}", "1.".bold().green())));
+    }
+}
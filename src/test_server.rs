@@ -0,0 +1,32 @@
+// A stand-in for a source site (or a proxy), for tests of the library and the binary alike
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+
+// Answer each request as told, for as long as the test runs
+// The response is everything after "HTTP/1.1 ": status line, headers, and body
+// Gives the base URL, and each request as it came in
+pub fn serve(
+    respond: impl Fn(&str) -> String + Send + 'static,
+) -> (String, mpsc::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+
+            let response = respond(&request);
+            let _sent = tx.send(request);
+            let _written = write!(stream, "HTTP/1.1 {response}");
+        }
+    });
+
+    (base, rx)
+}